@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use amplify::hex::ToHex;
+use argon2::Argon2;
+use bdk::bitcoin::hashes::{sha256, Hash};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use clap::{Subcommand, ValueHint};
+use rand::RngCore;
+use serde_crate::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cmds::runtime::RuntimeError;
+use crate::opts::RGB_DATA_DIR;
+use crate::utils::prepare_home_dir;
+
+/// Magic bytes starting every backup archive.
+const BACKUP_MAGIC: &[u8; 8] = b"RGBCLIBK";
+
+/// Version of the archive format, increased on each incompatible change.
+const BACKUP_VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Sled database of a wallet, in the directory named after the wallet.
+const WALLET_DB: &str = "wallet.sqlite";
+
+/// Environment variable with the passphrase of the archive.
+const PASSWORD_ENV: &str = "RGB_BACKUP_PASSWORD";
+
+#[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
+pub enum BackupSubCommand {
+    /// Creates an encrypted archive of the RGB stash and the wallet databases.
+    Create {
+        /// Backup file to create.
+        file: PathBuf,
+
+        #[clap(flatten)]
+        locations: BackupLocations,
+
+        #[clap(flatten)]
+        password: BackupPassword,
+    },
+    /// Restores the RGB stash and the wallet databases from an encrypted archive.
+    Restore {
+        /// Backup file to restore.
+        file: PathBuf,
+
+        #[clap(flatten)]
+        locations: BackupLocations,
+
+        #[clap(flatten)]
+        password: BackupPassword,
+
+        /// Replace the RGB data and the wallets which already exist.
+        #[clap(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, clap::Args, Clone, PartialEq, Eq)]
+pub struct BackupLocations {
+    /// Data directory path.
+    ///
+    /// Path to the directory that contains RGB stored data.
+    #[clap(
+        short = 'd',
+        long,
+        default_value = RGB_DATA_DIR,
+        value_hint = ValueHint::DirPath
+    )]
+    pub data_dir: PathBuf,
+
+    /// Sets the wallet data directory.
+    /// Default value : "~/.bdk-bitcoin
+    #[clap(long = "walletdir", value_hint = ValueHint::DirPath)]
+    pub walletdir: Option<PathBuf>,
+}
+
+/// Passphrase of the archive.
+///
+/// It is never taken from the command line, where other users could read it
+/// from the process list or the shell history.
+#[derive(Debug, clap::Args, Clone, PartialEq, Eq)]
+pub struct BackupPassword {
+    /// File whose first line is the passphrase, used when the
+    /// RGB_BACKUP_PASSWORD environment variable is not set.
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    pub password_file: Option<PathBuf>,
+}
+
+impl BackupPassword {
+    fn read(&self) -> Result<String, anyhow::Error> {
+        if let Ok(password) = std::env::var(PASSWORD_ENV) {
+            return non_empty(password, PASSWORD_ENV);
+        }
+        let file = self.password_file.as_ref().ok_or_else(|| {
+            RuntimeError::usage(format!(
+                "set the {PASSWORD_ENV} environment variable or use --password-file"
+            ))
+        })?;
+        let password = fs::read_to_string(file)?;
+        let password = password.lines().next().unwrap_or_default();
+        non_empty(
+            password.trim_end_matches('\r').to_owned(),
+            &file.display().to_string(),
+        )
+    }
+}
+
+/// Refuses an empty passphrase, which would leave the archive unprotected.
+fn non_empty(password: String, source: &str) -> Result<String, anyhow::Error> {
+    if password.is_empty() {
+        return Err(RuntimeError::usage(format!("the passphrase in {source} is empty")).into());
+    }
+    Ok(password)
+}
+
+/// Archive root a backed up file belongs to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "lowercase")]
+enum BackupRoot {
+    /// RGB data directory with the stock, seal secrets and wallet registry.
+    Rgb,
+    /// BDK wallet databases.
+    Wallets,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct BackupEntry {
+    root: BackupRoot,
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct BackupManifest {
+    version: u8,
+    created: u64,
+    files: Vec<BackupEntry>,
+}
+
+pub fn handle_backup_subcommand(
+    subcommand: BackupSubCommand,
+) -> Result<serde_json::Value, anyhow::Error> {
+    match subcommand {
+        BackupSubCommand::Create {
+            file,
+            locations,
+            password,
+        } => {
+            let password = password.read()?;
+            let wallet_dir = prepare_home_dir(locations.walletdir)?;
+            // Wallet databases stay open, and so locked against writers, until
+            // all their files are read
+            let _wallets = open_wallet_dbs(&wallet_dir)?;
+            let mut files = vec![];
+            collect_files(BackupRoot::Rgb, &locations.data_dir, &mut files)?;
+            collect_files(BackupRoot::Wallets, &wallet_dir, &mut files)?;
+            if !files.iter().any(|(entry, _)| {
+                entry.root == BackupRoot::Rgb && entry.path.ends_with("stock.dat")
+            }) {
+                return Err(RuntimeError::Custom(format!(
+                    "no RGB stock found in '{}'",
+                    locations.data_dir.display()
+                ))
+                .into());
+            }
+
+            let manifest = BackupManifest {
+                version: BACKUP_VERSION,
+                created: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs(),
+                files: files.iter().map(|(entry, _)| entry.clone()).collect(),
+            };
+            let manifest_data = serde_json::to_vec(&manifest)?;
+            let mut plaintext = (manifest_data.len() as u32).to_le_bytes().to_vec();
+            plaintext.extend(manifest_data);
+            for (_, data) in &files {
+                plaintext.extend(data);
+            }
+
+            let mut salt = [0u8; SALT_LEN];
+            let mut nonce = [0u8; NONCE_LEN];
+            rand::thread_rng().fill_bytes(&mut salt);
+            rand::thread_rng().fill_bytes(&mut nonce);
+            let cipher = backup_cipher(&password, &salt)?;
+            let ciphertext = cipher
+                .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+                .map_err(|_| RuntimeError::Internal("unable to encrypt the backup".to_owned()))?;
+
+            let mut archive = BACKUP_MAGIC.to_vec();
+            archive.push(BACKUP_VERSION);
+            archive.extend(salt);
+            archive.extend(nonce);
+            archive.extend(ciphertext);
+            fs::write(&file, archive)?;
+
+            Ok(json!({
+                "file": file,
+                "version": BACKUP_VERSION,
+                "files": manifest.files,
+            }))
+        }
+        BackupSubCommand::Restore {
+            file,
+            locations,
+            password,
+            force,
+        } => {
+            let password = password.read()?;
+            let archive = fs::read(&file)?;
+            let header_len = BACKUP_MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+            if archive.len() < header_len || &archive[..BACKUP_MAGIC.len()] != BACKUP_MAGIC {
+                return Err(RuntimeError::usage(format!(
+                    "'{}' is not a backup archive",
+                    file.display()
+                ))
+                .into());
+            }
+            let version = archive[BACKUP_MAGIC.len()];
+            if version != BACKUP_VERSION {
+                return Err(RuntimeError::unsupported(format!(
+                    "unsupported backup version {version}"
+                ))
+                .into());
+            }
+            let (salt, rest) = archive[BACKUP_MAGIC.len() + 1..].split_at(SALT_LEN);
+            let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+            let cipher = backup_cipher(&password, salt)?;
+            let plaintext = cipher
+                .decrypt(XNonce::from_slice(nonce), ciphertext)
+                .map_err(|_| RuntimeError::usage("wrong password or corrupted backup"))?;
+
+            let (manifest_len, rest) = plaintext
+                .split_first_chunk::<4>()
+                .ok_or_else(|| RuntimeError::usage("backup manifest is missing"))?;
+            let manifest_len = u32::from_le_bytes(*manifest_len) as usize;
+            if rest.len() < manifest_len {
+                return Err(RuntimeError::usage("backup manifest is truncated").into());
+            }
+            let (manifest, mut data) = rest.split_at(manifest_len);
+            let manifest = serde_json::from_slice::<BackupManifest>(manifest)?;
+
+            // Check the whole archive before touching any existing file
+            let wallet_dir = prepare_home_dir(locations.walletdir)?;
+            let mut units = BTreeMap::<PathBuf, Vec<(PathBuf, &[u8])>>::new();
+            for entry in &manifest.files {
+                if data.len() < entry.size as usize {
+                    return Err(RuntimeError::usage(format!(
+                        "backup data of '{}' is truncated",
+                        entry.path
+                    ))
+                    .into());
+                }
+                let (content, rest) = data.split_at(entry.size as usize);
+                data = rest;
+                if sha256::Hash::hash(content).to_byte_array().to_hex() != entry.sha256 {
+                    return Err(RuntimeError::usage(format!(
+                        "checksum mismatch for '{}'",
+                        entry.path
+                    ))
+                    .into());
+                }
+                let relative = Path::new(&entry.path);
+                if !relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(RuntimeError::usage(format!(
+                        "invalid path '{}' in backup",
+                        entry.path
+                    ))
+                    .into());
+                }
+                // Files are restored by the network directory or the wallet
+                // database they belong to
+                let mut components = relative.components();
+                let unit = components.next().expect("checked above").as_os_str();
+                let root = match entry.root {
+                    BackupRoot::Rgb => &locations.data_dir,
+                    BackupRoot::Wallets => &wallet_dir,
+                };
+                units
+                    .entry(root.join(unit))
+                    .or_default()
+                    .push((components.as_path().to_path_buf(), content));
+            }
+            if !data.is_empty() {
+                return Err(RuntimeError::usage("backup contains unexpected trailing data").into());
+            }
+            for dest in units.keys() {
+                if dest.exists() && !force {
+                    return Err(RuntimeError::usage(format!(
+                        "'{}' already exists, use --force to replace it",
+                        dest.display()
+                    ))
+                    .into());
+                }
+            }
+
+            for (dest, files) in &units {
+                restore_unit(dest, files)?;
+            }
+
+            Ok(json!({
+                "file": file,
+                "version": manifest.version,
+                "created": manifest.created,
+                "files": manifest.files,
+            }))
+        }
+    }
+}
+
+/// Derives the archive encryption key from the passphrase.
+fn backup_cipher(password: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, anyhow::Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|err| RuntimeError::Internal(format!("unable to derive the backup key: {err}")))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Reads all the files below `dir`, with their paths relative to it.
+fn collect_files(
+    root: BackupRoot,
+    dir: &Path,
+    files: &mut Vec<(BackupEntry, Vec<u8>)>,
+) -> Result<(), anyhow::Error> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let mut entries = fs::read_dir(&current)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.path());
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let data = fs::read(&path)?;
+            let relative = path
+                .strip_prefix(dir)?
+                .to_str()
+                .ok_or_else(|| {
+                    RuntimeError::unsupported(format!("non UTF-8 path '{}'", path.display()))
+                })?
+                .to_owned();
+            files.push((
+                BackupEntry {
+                    root,
+                    path: relative,
+                    size: data.len() as u64,
+                    sha256: sha256::Hash::hash(&data).to_byte_array().to_hex(),
+                },
+                data,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Opens and flushes the sled databases of all the wallets, failing if a
+/// wallet is used by another process.
+fn open_wallet_dbs(wallet_dir: &Path) -> Result<Vec<sled::Db>, anyhow::Error> {
+    let mut dbs = vec![];
+    if !wallet_dir.exists() {
+        return Ok(dbs);
+    }
+    for entry in fs::read_dir(wallet_dir)? {
+        let path = entry?.path().join(WALLET_DB);
+        if !path.join("conf").is_file() {
+            continue;
+        }
+        let db = sled::Config::new().path(&path).open().map_err(|err| {
+            RuntimeError::Wallet(bdk::Error::Generic(format!(
+                "unable to open wallet database '{}', it may be used by another process: {err}",
+                path.display()
+            )))
+        })?;
+        db.flush()?;
+        dbs.push(db);
+    }
+    Ok(dbs)
+}
+
+/// Writes the files of a network directory or a wallet database next to its
+/// destination and then moves them into place, so the restored data never
+/// mixes with the files it replaces.
+fn restore_unit(dest: &Path, files: &[(PathBuf, &[u8])]) -> Result<(), anyhow::Error> {
+    let name = dest
+        .file_name()
+        .ok_or_else(|| RuntimeError::usage(format!("invalid restore path '{}'", dest.display())))?
+        .to_string_lossy();
+    let parent = dest.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    let tmp = parent.join(format!(".{name}.restore"));
+    let old = parent.join(format!(".{name}.old"));
+    for path in [&tmp, &old] {
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    for (relative, content) in files {
+        // A file stored directly in the root is its own unit
+        let path = if relative.as_os_str().is_empty() {
+            tmp.clone()
+        } else {
+            tmp.join(relative)
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+
+    if dest.exists() {
+        fs::rename(dest, &old)?;
+    }
+    fs::rename(&tmp, dest)?;
+    if old.is_dir() {
+        fs::remove_dir_all(&old)?;
+    } else if old.exists() {
+        fs::remove_file(&old)?;
+    }
+    Ok(())
+}
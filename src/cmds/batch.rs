@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use bdk::bitcoin::Network;
+use clap::{Args, ValueHint};
+use psbt::PsbtVer;
+use serde_crate::Deserialize;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::session::{Session, SessionCommand, SessionOpts};
+use crate::opts::OutputFormat;
+use crate::output::render;
+
+const BINARY_NAME: &str = "bihelix-rgb-cli";
+
+/// Options of the batch command
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct BatchOpts {
+    /// File with the commands, or `-` to read them from the standard input.
+    ///
+    /// Either one command per line, written as on the command line after the
+    /// global options, or a JSON array with a string or an array of arguments
+    /// per command. Empty lines and lines starting with `#` are skipped.
+    #[clap(value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+
+    /// Runs the remaining commands after a failure instead of stopping.
+    #[clap(long)]
+    pub keep_going: bool,
+
+    #[clap(flatten)]
+    pub session: SessionOpts,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate", untagged)]
+enum BatchEntry {
+    Line(String),
+    Args(Vec<String>),
+}
+
+/// Runs the commands of a batch file against a single stash and wallet set.
+///
+/// Each command prints one result object as soon as it completes. When a
+/// command fails, the process exits with the code of the first failure.
+pub fn handle_batch(
+    network: Network,
+    psbt_version: Option<PsbtVer>,
+    output: OutputFormat,
+    opts: BatchOpts,
+) -> Result<JsonValue, anyhow::Error> {
+    let input = if opts.file.as_os_str() == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        fs::read_to_string(&opts.file)?
+    };
+    let commands = parse_batch(&input)?;
+    let session = Session::open(network, psbt_version, opts.session)?;
+
+    let mut stdout = io::stdout().lock();
+    let mut failure = None;
+    for (index, args) in commands.into_iter().enumerate() {
+        let command = args.join(" ");
+        log::debug!("batch command {index}: {command}");
+        let result = SessionCommand::parse_args(&args)
+            .map_err(|err| RuntimeError::usage(err.render().to_string().trim_end()))
+            .and_then(|command| session.run(command).map_err(RuntimeError::classify));
+        let entry = match &result {
+            Ok(value) => json!({ "index": index, "command": command, "result": value }),
+            Err(err) => {
+                let mut entry = err.to_json();
+                entry["index"] = json!(index);
+                entry["command"] = json!(command);
+                entry
+            }
+        };
+        match output {
+            OutputFormat::Json => writeln!(stdout, "{}", serde_json::to_string(&entry)?)?,
+            OutputFormat::Yaml => writeln!(stdout, "---\n{}", render(&entry, output)?)?,
+            OutputFormat::Text => writeln!(stdout, "{}\n", render(&entry, output)?)?,
+        }
+        stdout.flush()?;
+
+        if let Err(err) = result {
+            failure.get_or_insert(err);
+            if !opts.keep_going {
+                break;
+            }
+        }
+    }
+
+    match failure {
+        Some(err) => Err(err.into()),
+        None => Ok(JsonValue::Null),
+    }
+}
+
+/// Reads the commands of a batch as lists of arguments.
+fn parse_batch(input: &str) -> Result<Vec<Vec<String>>, RuntimeError> {
+    let commands = if input.trim_start().starts_with('[') {
+        let entries = serde_json::from_str::<Vec<BatchEntry>>(input)
+            .map_err(|err| RuntimeError::usage(format!("invalid JSON batch: {err}")))?;
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                BatchEntry::Line(line) => split_args(&line),
+                BatchEntry::Args(args) => Ok(args),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(split_args)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    Ok(commands
+        .into_iter()
+        .map(|mut args| {
+            // Commands copied from scripts may keep the binary name
+            if args.first().is_some_and(|arg| arg == BINARY_NAME) {
+                args.remove(0);
+            }
+            args
+        })
+        .filter(|args| !args.is_empty())
+        .collect())
+}
+
+/// Splits a command line into arguments like a POSIX shell does for plain
+/// words: whitespace separates the arguments, single quotes keep the text as
+/// is, double quotes and backslashes escape the whitespace and the quotes.
+pub fn split_args(line: &str) -> Result<Vec<String>, RuntimeError> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(RuntimeError::usage("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(RuntimeError::usage("unterminated double quote")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(RuntimeError::usage("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                match chars.next() {
+                    Some(c) => arg.push(c),
+                    None => return Err(RuntimeError::usage("trailing backslash")),
+                }
+            }
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bdk::bitcoin::hashes::Hash;
+use bdk::database::BatchDatabase;
+use bdk::Wallet;
+use bp::seals::txout::TxoSeal;
+use bp::{Outpoint, Txid};
+use rgb::{Assign, ExposedSeal, Opout, RevealedValue, TypedAssigns, WitnessOrd};
+use rgbstd::contract::{ContractId, GraphSeal};
+use rgbstd::persistence::{Inventory, Stash};
+use rgbstd::resolvers::ResolveHeight;
+use rgbstd::{Operation, SecretSeal, XChain};
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::runtime::Runtime;
+
+/// Outputs and blinded seals considered to be owned by the wallet.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Ownership {
+    outpoints: BTreeSet<Outpoint>,
+    secret_seals: BTreeSet<SecretSeal>,
+}
+
+impl Ownership {
+    /// Collects the seals the wallet has generated for invoices and change.
+    pub fn with_seal_secrets(seals: impl IntoIterator<Item = XChain<GraphSeal>>) -> Self {
+        let mut ownership = Ownership::default();
+        for seal in seals {
+            if let Some(outpoint) = seal.outpoint() {
+                ownership.outpoints.insert(outpoint);
+            }
+            ownership
+                .secret_seals
+                .insert(*seal.to_secret_seal().as_reduced_unsafe());
+        }
+        ownership
+    }
+
+    pub fn add_outpoints(&mut self, outpoints: impl IntoIterator<Item = Outpoint>) {
+        self.outpoints.extend(outpoints);
+    }
+
+    /// Adds the outputs of all the transactions of a synced wallet which pay
+    /// to the wallet descriptors.
+    pub fn add_wallet<D: BatchDatabase>(&mut self, wallet: &Wallet<D>) -> Result<(), bdk::Error> {
+        for details in wallet.list_transactions(true)? {
+            let Some(tx) = details.transaction else {
+                continue;
+            };
+            let txid = Txid::from(details.txid.to_byte_array());
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if wallet.is_mine(&txout.script_pubkey)? {
+                    self.outpoints.insert(Outpoint::new(txid, vout as u32));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn owner(&self, outpoint: Option<Outpoint>, secret: SecretSeal) -> &'static str {
+        if outpoint.is_some_and(|outpoint| self.outpoints.contains(&outpoint))
+            || self.secret_seals.contains(&secret)
+        {
+            "ours"
+        } else {
+            "theirs"
+        }
+    }
+}
+
+/// Lists all operations known for a contract, oldest first.
+///
+/// Each operation reports the state it spends and creates, attributed to the
+/// wallet (`ours`) or to other parties (`theirs`) using the given ownership.
+/// Transitions whose anchor can't be read from the stash are listed with an
+/// `error` instead of failing the whole history, and those whose witness
+/// can't be resolved with a `height_error` next to the null `height`.
+pub fn contract_history(
+    runtime: &Runtime,
+    resolver: &mut impl ResolveHeight,
+    contract_id: ContractId,
+    ownership: &Ownership,
+) -> Result<JsonValue, anyhow::Error> {
+    let schema_ifaces = runtime.contract_schema(contract_id)?;
+    let assignment_names = schema_ifaces
+        .iimpls
+        .values()
+        .flat_map(|iimpl| iimpl.assignments.iter())
+        .map(|info| (info.id, info.name.to_string()))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut outputs = BTreeMap::<Opout, JsonValue>::new();
+    let mut operations = vec![];
+
+    let genesis = runtime.genesis(contract_id)?;
+    operations.push(json!({
+        "kind": "genesis",
+        "opid": genesis.id().to_string(),
+        "witness": null,
+        "height": null,
+        "outputs": assignments_json(
+            genesis.id(),
+            genesis.assignments.iter(),
+            None,
+            ownership,
+            &assignment_names,
+            &mut outputs,
+        ),
+        "inputs": [],
+    }));
+
+    for opid in runtime.extension_ids()? {
+        let extension = runtime.extension(opid)?;
+        if extension.contract_id != contract_id {
+            continue;
+        }
+        operations.push(json!({
+            "kind": "extension",
+            "opid": opid.to_string(),
+            "extension_type": extension.extension_type,
+            "witness": null,
+            "height": null,
+            "outputs": assignments_json(
+                opid,
+                extension.assignments.iter(),
+                None,
+                ownership,
+                &assignment_names,
+                &mut outputs,
+            ),
+            "inputs": [],
+        }));
+    }
+
+    let mut transitions = vec![];
+    for bundle_id in runtime.bundle_ids()? {
+        let bundle = runtime.bundle(bundle_id)?;
+        for (opid, transition) in bundle.known_transitions.iter() {
+            if transition.contract_id != contract_id {
+                continue;
+            }
+            // A transition without its anchor is still listed, with the error
+            let (witness, height, height_error, error) = match runtime.anchored_bundle(*opid) {
+                Ok(anchored) => {
+                    let anchor = anchored.anchor;
+                    let witness = anchor.witness_id().map(|id| *id.as_reduced_unsafe());
+                    // Unmined witness transactions have no height
+                    match resolver.resolve_anchor(&anchor) {
+                        Ok(anchor) => {
+                            let height = match anchor.witness_ord {
+                                WitnessOrd::OnChain(pos) => Some(pos.height().get()),
+                                WitnessOrd::OffChain => None,
+                            };
+                            (witness, height, None, None)
+                        }
+                        Err(err) => (witness, None, Some(err.to_string()), None),
+                    }
+                }
+                Err(err) => (None, None, None, Some(err.to_string())),
+            };
+            transitions.push((
+                height,
+                *opid,
+                transition.clone(),
+                witness,
+                height_error,
+                error,
+            ));
+        }
+    }
+    transitions.sort_by_key(|(height, opid, ..)| (height.is_none(), *height, *opid));
+
+    let mut spent = vec![];
+    for (height, opid, transition, witness, height_error, error) in transitions {
+        let created = assignments_json(
+            opid,
+            transition.assignments.iter(),
+            witness,
+            ownership,
+            &assignment_names,
+            &mut outputs,
+        );
+        spent.push(
+            transition
+                .inputs
+                .iter()
+                .map(|input| input.prev_out)
+                .collect::<Vec<_>>(),
+        );
+        let mut operation = json!({
+            "kind": "transition",
+            "opid": opid.to_string(),
+            "transition_type": transition.transition_type,
+            "witness": witness.map(|txid| txid.to_string()),
+            "height": height,
+            "outputs": created,
+        });
+        if let Some(height_error) = height_error {
+            operation["height_error"] = json!(height_error);
+        }
+        if let Some(error) = error {
+            operation["error"] = json!(error);
+        }
+        operations.push(operation);
+    }
+
+    // Inputs are resolved once all the outputs they may spend are known
+    for (operation, prev_outs) in operations
+        .iter_mut()
+        .filter(|op| op["kind"] == "transition")
+        .zip(spent)
+    {
+        operation["inputs"] = prev_outs
+            .into_iter()
+            .map(|prev_out| {
+                outputs.get(&prev_out).cloned().unwrap_or_else(|| {
+                    json!({
+                        "opout": prev_out.to_string(),
+                        "owner": "unknown",
+                    })
+                })
+            })
+            .collect();
+    }
+
+    for operation in &mut operations {
+        let ours = |key: &str| {
+            operation[key]
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter(|item| item["owner"] == "ours")
+                        .filter_map(|item| item["amount"].as_u64())
+                        .sum::<u64>()
+                })
+                .unwrap_or_default()
+        };
+        let (ours_in, ours_out) = (ours("inputs"), ours("outputs"));
+        operation["ours_net"] = json!(ours_out as i128 - ours_in as i128);
+    }
+
+    Ok(json!({
+        "contract_id": contract_id.to_string(),
+        "operations": operations,
+    }))
+}
+
+fn assignments_json<'a, Seal: ExposedSeal + 'a>(
+    opid: rgb::OpId,
+    assignments: impl Iterator<Item = (&'a rgb::AssignmentType, &'a TypedAssigns<Seal>)>,
+    witness: Option<Txid>,
+    ownership: &Ownership,
+    names: &BTreeMap<rgb::AssignmentType, String>,
+    outputs: &mut BTreeMap<Opout, JsonValue>,
+) -> Vec<JsonValue> {
+    let mut created = vec![];
+    for (ty, assigns) in assignments {
+        for no in 0..assigns.len_u16() {
+            let opout = Opout::new(opid, *ty, no);
+            let amount = assigns
+                .as_fungible()
+                .get(no as usize)
+                .and_then(Assign::<RevealedValue, Seal>::as_revealed_state)
+                .map(|state| state.value.as_u64());
+            let revealed = assigns.revealed_seal_at(no).ok().flatten();
+            let outpoint = revealed.and_then(|seal| match witness {
+                Some(txid) => Some(seal.outpoint_or(txid)),
+                None => seal.outpoint(),
+            });
+            let secret = assigns.to_confidential_seals()[no as usize];
+            let output = json!({
+                "opout": opout.to_string(),
+                "assignment": names.get(ty),
+                "seal": match outpoint {
+                    Some(outpoint) => outpoint.to_string(),
+                    None => secret.to_string(),
+                },
+                "amount": amount,
+                "owner": ownership.owner(outpoint, *secret.as_reduced_unsafe()),
+            });
+            outputs.insert(opout, output.clone());
+            created.push(output);
+        }
+    }
+    created
+}
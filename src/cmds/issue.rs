@@ -0,0 +1,1234 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::cmds::runtime::RuntimeError;
+use amplify::confinement::{TinyOrdMap, U16};
+use amplify::{Display, Wrapper};
+use bdk::bitcoin::hashes::{sha256, Hash};
+use bp::seals::txout::CloseMethod;
+use bp::Outpoint;
+use clap::{Args, ValueEnum, ValueHint};
+use rgb::{OutputSeal, StateSchema};
+use rgb_schemata::{cfa_rgb25, cfa_schema, nia_rgb20, nia_schema, uda_rgb21, uda_schema};
+use rgbstd::containers::Contract;
+use rgbstd::interface::rgb21::{Allocation, OwnedFraction, TokenData, TokenIndex};
+use rgbstd::interface::{
+    rgb20, rgb21, rgb25, ArgMap, ArgSpec, ContractBuilder, Iface, IfaceId, IfaceImpl, SchemaIfaces,
+};
+use rgbstd::invoice::{Amount, Precision};
+use rgbstd::persistence::{Stash, Stock};
+use rgbstd::schema::SchemaId;
+use rgbstd::stl::{
+    Attachment, ContractData, Details, DivisibleAssetSpec, MediaRegName, MediaType, Name,
+    RicardianContract, Ticker, Timestamp,
+};
+use rgbstd::{GenesisSeal, SubSchema, XChain};
+use serde_yaml::{Mapping, Value};
+use strict_types::encoding::constants::UNIT;
+use strict_types::encoding::{FieldName, NumCls, Primitive, Sizing, TypeName};
+use strict_types::typify::TypedVal;
+use strict_types::{SemId, StrictVal, Ty, TypeSystem};
+
+/// Contract data common to all the built-in asset interfaces
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct IssueOpts {
+    /// Asset name
+    #[clap(long)]
+    pub name: String,
+
+    /// Asset details
+    #[clap(long)]
+    pub details: Option<String>,
+
+    /// Text of the contract terms
+    #[clap(long)]
+    pub terms: Option<String>,
+
+    /// File attached to the contract, committed to by its SHA256 hash
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    pub media: Option<PathBuf>,
+
+    /// MIME type of the media file, guessed from its extension if omitted
+    #[clap(long, requires = "media")]
+    pub media_type: Option<String>,
+
+    /// Allocation of the issued asset as TXID:VOUT=AMOUNT
+    #[clap(short, long = "allocation", required = true, value_parser = parse_allocation)]
+    pub allocations: Vec<(Outpoint, u64)>,
+
+    /// Method for the genesis single-use-seals.
+    #[clap(long, default_value = "opret1st")]
+    pub method: CloseMethod,
+
+    /// File to export the issued contract to
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub out: Option<PathBuf>,
+}
+
+/// Parse an allocation (Outpoint=Amount) argument from cli input.
+fn parse_allocation(s: &str) -> Result<(Outpoint, u64), String> {
+    let (outpoint, amount) = s
+        .rsplit_once('=')
+        .ok_or_else(|| "invalid format, expected TXID:VOUT=AMOUNT".to_string())?;
+    let outpoint = Outpoint::from_str(outpoint).map_err(|e| e.to_string())?;
+    let amount = u64::from_str(amount).map_err(|e| e.to_string())?;
+    Ok((outpoint, amount))
+}
+
+impl IssueOpts {
+    fn seals(&self) -> impl Iterator<Item = (XChain<GenesisSeal>, u64)> + '_ {
+        self.allocations.iter().map(|(outpoint, amount)| {
+            let seal = GenesisSeal::new_random(self.method, outpoint.txid, outpoint.vout);
+            (XChain::Bitcoin(seal), *amount)
+        })
+    }
+
+    fn issued_supply(&self) -> Result<Amount, RuntimeError> {
+        self.allocations
+            .iter()
+            .try_fold(0u64, |sum, (_, amount)| sum.checked_add(*amount))
+            .map(Amount::from)
+            .ok_or_else(|| RuntimeError::usage("total issued supply overflows"))
+    }
+
+    fn details(&self) -> Result<Option<Details>, RuntimeError> {
+        self.details
+            .clone()
+            .map(Details::try_from)
+            .transpose()
+            .map_err(|err| RuntimeError::usage(format!("invalid details: {err}")))
+    }
+
+    fn terms(&self) -> Result<RicardianContract, RuntimeError> {
+        RicardianContract::from_str(self.terms.as_deref().unwrap_or_default())
+            .map_err(|err| RuntimeError::usage(format!("invalid terms: {err}")))
+    }
+
+    fn attachment(&self) -> Result<Option<Attachment>, anyhow::Error> {
+        let Some(path) = &self.media else {
+            return Ok(None);
+        };
+        let data = fs::read(path)?;
+        let media_type = match &self.media_type {
+            Some(media_type) => media_type.as_str(),
+            None => guess_media_type(path),
+        };
+        let (ty, subtype) = media_type
+            .split_once('/')
+            .ok_or_else(|| RuntimeError::usage(format!("invalid media type '{media_type}'")))?;
+        let reg_name = |name: &str| {
+            MediaRegName::try_from(name.to_owned()).map_err(|err| {
+                RuntimeError::usage(format!("invalid media type '{media_type}': {err}"))
+            })
+        };
+        Ok(Some(Attachment {
+            ty: MediaType {
+                ty: reg_name(ty)?,
+                subtype: match subtype {
+                    "*" => None,
+                    subtype => Some(reg_name(subtype)?),
+                },
+                charset: None,
+            },
+            digest: sha256::Hash::hash(&data).to_byte_array(),
+        }))
+    }
+
+    fn contract_data(&self) -> Result<ContractData, anyhow::Error> {
+        Ok(ContractData {
+            terms: self.terms()?,
+            media: self.attachment()?,
+        })
+    }
+}
+
+fn guess_media_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("txt" | "md") => "text/plain",
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+fn builder(
+    iface: Iface,
+    schema: SubSchema,
+    iimpl: IfaceImpl,
+    mainnet: bool,
+) -> Result<ContractBuilder, anyhow::Error> {
+    let builder = if mainnet {
+        ContractBuilder::mainnet(iface, schema, iimpl)?
+    } else {
+        ContractBuilder::testnet(iface, schema, iimpl)?
+    };
+    Ok(builder)
+}
+
+fn precision(decimals: u8) -> Result<Precision, RuntimeError> {
+    Precision::try_from(decimals)
+        .map_err(|_| RuntimeError::usage("precision must be between 0 and 18"))
+}
+
+/// Issues a non-inflatable fungible asset (NIA schema) under the RGB20 interface.
+pub fn issue_rgb20(
+    ticker: &str,
+    decimals: u8,
+    opts: &IssueOpts,
+    mainnet: bool,
+) -> Result<Contract, anyhow::Error> {
+    let spec = DivisibleAssetSpec::with(
+        ticker,
+        &opts.name,
+        precision(decimals)?,
+        opts.details.as_deref(),
+    )
+    .map_err(|err| RuntimeError::usage(format!("invalid asset naming: {err}")))?;
+
+    let mut builder = builder(rgb20(), nia_schema(), nia_rgb20(), mainnet)?
+        .add_global_state("spec", spec)?
+        .add_global_state("created", Timestamp::now())?
+        .add_global_state("issuedSupply", opts.issued_supply()?)?
+        .add_global_state("data", opts.contract_data()?)?;
+    for (seal, amount) in opts.seals() {
+        builder = builder.add_fungible_state("assetOwner", seal, amount)?;
+    }
+    Ok(builder.issue_contract()?)
+}
+
+/// Issues a collectible fungible asset (CFA schema) under the RGB25 interface.
+pub fn issue_rgb25(
+    decimals: u8,
+    opts: &IssueOpts,
+    mainnet: bool,
+) -> Result<Contract, anyhow::Error> {
+    let name = Name::try_from(opts.name.clone())
+        .map_err(|err| RuntimeError::usage(format!("invalid name: {err}")))?;
+
+    let mut builder =
+        builder(rgb25(), cfa_schema(), cfa_rgb25(), mainnet)?.add_global_state("name", name)?;
+    if let Some(details) = opts.details()? {
+        builder = builder.add_global_state("details", details)?;
+    }
+    builder = builder
+        .add_global_state("precision", precision(decimals)?)?
+        .add_global_state("created", Timestamp::now())?
+        .add_global_state("issuedSupply", opts.issued_supply()?)?
+        .add_global_state("data", opts.contract_data()?)?;
+    for (seal, amount) in opts.seals() {
+        builder = builder.add_fungible_state("assetOwner", seal, amount)?;
+    }
+    Ok(builder.issue_contract()?)
+}
+
+/// Issues a unique digital asset (UDA schema) under the RGB21 interface.
+///
+/// The contract has a single token, whose fractions are distributed over the
+/// allocations.
+pub fn issue_rgb21(
+    ticker: &str,
+    opts: &IssueOpts,
+    mainnet: bool,
+) -> Result<Contract, anyhow::Error> {
+    let spec = DivisibleAssetSpec::with(
+        ticker,
+        &opts.name,
+        Precision::Indivisible,
+        opts.details.as_deref(),
+    )
+    .map_err(|err| RuntimeError::usage(format!("invalid asset naming: {err}")))?;
+    let index = TokenIndex::from_inner(0);
+    let token = TokenData {
+        index,
+        ticker: Some(
+            Ticker::try_from(ticker.to_owned())
+                .map_err(|err| RuntimeError::usage(format!("invalid ticker: {err}")))?,
+        ),
+        name: Some(
+            Name::try_from(opts.name.clone())
+                .map_err(|err| RuntimeError::usage(format!("invalid name: {err}")))?,
+        ),
+        details: opts.details()?,
+        media: opts.attachment()?,
+        ..Default::default()
+    };
+
+    let mut builder = builder(rgb21(), uda_schema(), uda_rgb21(), mainnet)?
+        .add_global_state("tokens", token)?
+        .add_global_state("spec", spec)?
+        .add_global_state("created", Timestamp::now())?
+        .add_global_state("terms", opts.terms()?)?;
+    for (seal, fraction) in opts.seals() {
+        let allocation = Allocation::with(index, OwnedFraction::from_inner(fraction));
+        builder = builder.add_data("assetOwner", seal, allocation)?;
+    }
+    Ok(builder.issue_contract()?)
+}
+
+/// Format of issuance files and of the templates generated for them
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[display(lowercase)]
+pub enum IssueFormat {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+}
+
+/// Problem found in an issuance file
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display("{path}: {message}")]
+pub struct IssueFileError {
+    /// Location of the problem, like `globals.spec.naming.ticker`
+    pub path: String,
+    pub message: String,
+}
+
+impl IssueFileError {
+    fn new(path: &str, message: impl ToString) -> Self {
+        IssueFileError {
+            path: if path.is_empty() {
+                "<root>".to_owned()
+            } else {
+                path.to_owned()
+            },
+            message: message.to_string(),
+        }
+    }
+
+    fn mismatch(path: &str, expected: String, found: &Value) -> Self {
+        let found = match found {
+            Value::Null => "null".to_owned(),
+            Value::Bool(val) => val.to_string(),
+            Value::Number(no) => no.to_string(),
+            Value::String(s) => format!("'{s}'"),
+            Value::Sequence(_) => "list".to_owned(),
+            Value::Mapping(_) => "mapping".to_owned(),
+            Value::Tagged(_) => "tagged value".to_owned(),
+        };
+        IssueFileError::new(path, format!("expected {expected}, found {found}"))
+    }
+}
+
+/// Maximal depth of nested types described in templates and error messages.
+const MAX_TYPE_DEPTH: usize = 8;
+
+/// Placeholder seal used by the templates.
+const TEMPLATE_SEAL: &str =
+    "opret1st:0000000000000000000000000000000000000000000000000000000000000000:0";
+
+fn occurrences(spec: &ArgSpec) -> &'static str {
+    match (spec.req.min_value(), spec.req.max_value()) {
+        (0, 1) => "optional",
+        (1, 1) => "required",
+        (0, _) => "optional, multiple",
+        _ => "required, multiple",
+    }
+}
+
+/// Generates a skeleton issuance file listing all the global state and
+/// assignments of the interface, together with their types.
+pub fn issue_template(
+    stash: &Stock,
+    schema_id: SchemaId,
+    iface_name: &str,
+    format: IssueFormat,
+) -> Result<String, anyhow::Error> {
+    let SchemaIfaces { schema, iimpls } = stash.schema(schema_id)?;
+    let iface = find_iface(stash, iface_name)
+        .ok_or_else(|| RuntimeError::usage(format!("unknown interface '{iface_name}'")))?;
+    let iimpl = iimpls.get(&iface.iface_id()).ok_or_else(|| {
+        RuntimeError::usage(format!(
+            "schema {schema_id} doesn't implement interface {}",
+            iface.name
+        ))
+    })?;
+    let types = &schema.type_system;
+
+    let mut comments = vec![
+        format!("Issuance of a {} contract", iface.name),
+        format!("using schema {schema_id}."),
+        "".to_owned(),
+        "Global state:".to_owned(),
+    ];
+    let mut globals = Mapping::new();
+    for (alias, spec) in &iface.genesis.global {
+        let name = spec.name.as_ref().unwrap_or(alias);
+        let Some(sem_id) = global_sem_id(schema, iimpl, name) else {
+            continue;
+        };
+        comments.push(format!(
+            "  {alias} ({}): {}",
+            occurrences(spec),
+            describe(types, sem_id, 0)
+        ));
+        globals.insert(Value::from(alias.to_string()), skeleton(types, sem_id, 0));
+    }
+    comments.push("".to_owned());
+    comments.push("Assignments, each one or a list of:".to_owned());
+    let mut assignments = Mapping::new();
+    for (alias, spec) in &iface.genesis.assignments {
+        let name = spec.name.as_ref().unwrap_or(alias);
+        let Some(state_schema) = owned_state_schema(schema, iimpl, name) else {
+            continue;
+        };
+        let mut assignment = Mapping::new();
+        assignment.insert(Value::from("seal"), Value::from(TEMPLATE_SEAL));
+        let description = match state_schema {
+            StateSchema::Declarative => "seal only".to_owned(),
+            StateSchema::Fungible(_) => {
+                assignment.insert(Value::from("amount"), Value::from(0u64));
+                "seal and amount (unsigned 64-bit integer)".to_owned()
+            }
+            StateSchema::Structured(sem_id) => {
+                assignment.insert(Value::from("data"), skeleton(types, *sem_id, 0));
+                format!("seal and data ({})", describe(types, *sem_id, 0))
+            }
+            StateSchema::Attachment(_) => {
+                "attachments are not supported in issuance files".to_owned()
+            }
+        };
+        comments.push(format!("  {alias} ({}): {description}", occurrences(spec)));
+        assignments.insert(Value::from(alias.to_string()), Value::Mapping(assignment));
+    }
+    comments.push("".to_owned());
+    comments.push(
+        "Seals have METHOD:TXID:VOUT format, with method being opret1st or tapret1st.".to_owned(),
+    );
+
+    let mut code = Mapping::new();
+    if format == IssueFormat::Json {
+        code.insert(
+            Value::from("$comment"),
+            Value::Sequence(
+                comments
+                    .iter()
+                    .map(|line| Value::from(line.as_str()))
+                    .collect(),
+            ),
+        );
+    }
+    code.insert(
+        Value::from("interface"),
+        Value::from(iface.name.to_string()),
+    );
+    code.insert(Value::from("globals"), Value::Mapping(globals));
+    code.insert(Value::from("assignments"), Value::Mapping(assignments));
+    let code = Value::Mapping(code);
+
+    let header = comments
+        .iter()
+        .map(|line| format!("# {line}").trim_end().to_owned())
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(match format {
+        IssueFormat::Yaml => format!("{header}\n\n{}", serde_yaml::to_string(&code)?),
+        IssueFormat::Json => serde_json::to_string_pretty(&code)? + "\n",
+        IssueFormat::Toml => format!("{header}\n\n{}", toml::to_string(&strip_nulls(code))?),
+    })
+}
+
+/// Issues a contract from a YAML, JSON or TOML issuance file.
+///
+/// The whole file is checked before issuing, and all the errors found are
+/// reported at once.
+pub fn issue_from_file(
+    stash: &Stock,
+    schema_id: SchemaId,
+    file: &Path,
+    mainnet: bool,
+) -> Result<Contract, anyhow::Error> {
+    let data = fs::read_to_string(file)?;
+    let code = match file.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => serde_yaml::to_value(
+            toml::from_str::<toml::Value>(&data).map_err(RuntimeError::usage)?,
+        )?,
+        // JSON is a subset of YAML
+        _ => serde_yaml::from_str::<Value>(&data)?,
+    };
+    let SchemaIfaces { schema, iimpls } = stash.schema(schema_id)?;
+
+    let mut errors = vec![];
+    let contract = build_contract(stash, schema, iimpls, code, mainnet, &mut errors)?;
+    match contract {
+        Some(contract) if errors.is_empty() => Ok(contract),
+        _ => Err(RuntimeError::usage(format!(
+            "{} error(s) in '{}':\n  {}",
+            errors.len(),
+            file.display(),
+            errors
+                .iter()
+                .map(IssueFileError::to_string)
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        ))
+        .into()),
+    }
+}
+
+fn build_contract(
+    stash: &Stock,
+    schema: &SubSchema,
+    iimpls: &TinyOrdMap<IfaceId, IfaceImpl>,
+    code: Value,
+    mainnet: bool,
+    errors: &mut Vec<IssueFileError>,
+) -> Result<Option<Contract>, anyhow::Error> {
+    let Value::Mapping(mut code) = code else {
+        errors.push(IssueFileError::new(
+            "",
+            "expected a mapping with interface, globals and assignments",
+        ));
+        return Ok(None);
+    };
+    code.remove("$comment");
+    let iface_name = code.remove("interface");
+    let globals = code
+        .remove("globals")
+        .unwrap_or(Value::Mapping(Mapping::new()));
+    let assignments = code
+        .remove("assignments")
+        .unwrap_or(Value::Mapping(Mapping::new()));
+    for (key, _) in code {
+        errors.push(IssueFileError::new(
+            &value_key(&key),
+            "unknown section, expected interface, globals or assignments",
+        ));
+    }
+
+    let iface = match iface_name {
+        None => None,
+        Some(Value::String(name)) => find_iface(stash, &name),
+        Some(ref other) => {
+            errors.push(IssueFileError::mismatch(
+                "interface",
+                "interface name".to_owned(),
+                other,
+            ));
+            return Ok(None);
+        }
+    };
+    let Some(iface) = iface else {
+        let known = stash
+            .ifaces()?
+            .values()
+            .map(TypeName::to_string)
+            .collect::<Vec<_>>();
+        errors.push(IssueFileError::new(
+            "interface",
+            format!(
+                "missing or unknown interface, expected one of {}",
+                known.join(", ")
+            ),
+        ));
+        return Ok(None);
+    };
+    let Some(iimpl) = iimpls.get(&iface.iface_id()) else {
+        errors.push(IssueFileError::new(
+            "interface",
+            format!(
+                "schema {} doesn't implement interface {}",
+                schema.schema_id(),
+                iface.name
+            ),
+        ));
+        return Ok(None);
+    };
+    let types = &schema.type_system;
+
+    let mut global_state = vec![];
+    let Value::Mapping(globals) = globals else {
+        errors.push(IssueFileError::mismatch(
+            "globals",
+            "mapping of global state names to values".to_owned(),
+            &globals,
+        ));
+        return Ok(None);
+    };
+    for (alias, spec) in &iface.genesis.global {
+        if spec.req.min_value() > 0 && !globals.contains_key(alias.as_str()) {
+            errors.push(IssueFileError::new(
+                &format!("globals.{alias}"),
+                "missing required global state",
+            ));
+        }
+    }
+    for (key, val) in globals {
+        let path = format!("globals.{}", value_key(&key));
+        let Some((name, spec)) = arg_spec(&iface.genesis.global, &key) else {
+            errors.push(IssueFileError::new(&path, "unknown global state"));
+            continue;
+        };
+        let Some(sem_id) = global_sem_id(schema, iimpl, &name) else {
+            errors.push(IssueFileError::new(
+                &path,
+                "global state is not implemented by the schema",
+            ));
+            continue;
+        };
+        let values = match val {
+            Value::Sequence(list)
+                if spec.req.max_value() > 1
+                    && !types.find(sem_id).is_some_and(Ty::is_collection) =>
+            {
+                list.into_iter()
+                    .enumerate()
+                    .map(|(no, val)| (format!("{path}[{no}]"), val))
+                    .collect()
+            }
+            val => vec![(path, val)],
+        };
+        if values.len() > spec.req.max_value() as usize {
+            errors.push(IssueFileError::new(
+                &format!("globals.{}", value_key(&key)),
+                format!("at most {} values are allowed", spec.req.max_value()),
+            ));
+        }
+        for (path, val) in values {
+            if let Some(data) = typed_data(types, sem_id, val, &path, errors) {
+                global_state.push((name.clone(), data));
+            }
+        }
+    }
+
+    let mut owned_state = vec![];
+    let Value::Mapping(assignments) = assignments else {
+        errors.push(IssueFileError::mismatch(
+            "assignments",
+            "mapping of assignment names to seals and state".to_owned(),
+            &assignments,
+        ));
+        return Ok(None);
+    };
+    for (alias, spec) in &iface.genesis.assignments {
+        if spec.req.min_value() > 0 && !assignments.contains_key(alias.as_str()) {
+            errors.push(IssueFileError::new(
+                &format!("assignments.{alias}"),
+                "missing required assignment",
+            ));
+        }
+    }
+    for (key, val) in assignments {
+        let path = format!("assignments.{}", value_key(&key));
+        let Some((name, spec)) = arg_spec(&iface.genesis.assignments, &key) else {
+            errors.push(IssueFileError::new(&path, "unknown assignment"));
+            continue;
+        };
+        let Some(state_schema) = owned_state_schema(schema, iimpl, &name) else {
+            errors.push(IssueFileError::new(
+                &path,
+                "assignment is not implemented by the schema",
+            ));
+            continue;
+        };
+        let entries = match val {
+            Value::Sequence(list) => list
+                .into_iter()
+                .enumerate()
+                .map(|(no, val)| (format!("{path}[{no}]"), val))
+                .collect(),
+            val => vec![(path.clone(), val)],
+        };
+        if entries.len() > spec.req.max_value() as usize {
+            errors.push(IssueFileError::new(
+                &path,
+                format!("at most {} assignments are allowed", spec.req.max_value()),
+            ));
+        }
+        for (path, entry) in entries {
+            if let Some(state) = owned_data(types, state_schema, entry, &path, errors) {
+                owned_state.push((name.clone(), state));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = builder(iface.clone(), schema.clone(), iimpl.clone(), mainnet)?;
+    for (name, data) in global_state {
+        builder = builder.add_global_state(name, types.strict_serialize_type::<U16>(&data)?)?;
+    }
+    for (name, (seal, state)) in owned_state {
+        let seal = XChain::Bitcoin(GenesisSeal::new_random(seal.method, seal.txid, seal.vout));
+        builder = match state {
+            OwnedData::Rights => builder.add_rights(name, seal)?,
+            OwnedData::Amount(amount) => builder.add_fungible_state(name, seal, amount)?,
+            OwnedData::Data(data) => {
+                builder.add_data(name, seal, types.strict_serialize_type::<U16>(&data)?)?
+            }
+        };
+    }
+    Ok(Some(builder.issue_contract()?))
+}
+
+/// State of an assignment read from an issuance file
+enum OwnedData {
+    Rights,
+    Amount(u64),
+    Data(Box<TypedVal>),
+}
+
+fn owned_data(
+    types: &TypeSystem,
+    state_schema: &StateSchema,
+    entry: Value,
+    path: &str,
+    errors: &mut Vec<IssueFileError>,
+) -> Option<(OutputSeal, OwnedData)> {
+    let Value::Mapping(mut entry) = entry else {
+        errors.push(IssueFileError::mismatch(
+            path,
+            "mapping with seal and state".to_owned(),
+            &entry,
+        ));
+        return None;
+    };
+    let seal = match entry.remove("seal") {
+        Some(Value::String(seal)) => match OutputSeal::from_str(&seal) {
+            Ok(seal) => Some(seal),
+            Err(err) => {
+                errors.push(IssueFileError::new(
+                    &format!("{path}.seal"),
+                    format!("invalid seal '{seal}' ({err}), expected METHOD:TXID:VOUT"),
+                ));
+                None
+            }
+        },
+        Some(other) => {
+            errors.push(IssueFileError::mismatch(
+                &format!("{path}.seal"),
+                "seal as METHOD:TXID:VOUT".to_owned(),
+                &other,
+            ));
+            None
+        }
+        None => {
+            errors.push(IssueFileError::new(path, "missing seal"));
+            None
+        }
+    };
+    let state = match state_schema {
+        StateSchema::Declarative => Some(OwnedData::Rights),
+        StateSchema::Fungible(_) => match entry.remove("amount") {
+            Some(Value::Number(amount)) if amount.is_u64() => {
+                amount.as_u64().map(OwnedData::Amount)
+            }
+            Some(other) => {
+                errors.push(IssueFileError::mismatch(
+                    &format!("{path}.amount"),
+                    "unsigned 64-bit integer".to_owned(),
+                    &other,
+                ));
+                None
+            }
+            None => {
+                errors.push(IssueFileError::new(path, "missing amount"));
+                None
+            }
+        },
+        StateSchema::Structured(sem_id) => match entry.remove("data") {
+            Some(data) => typed_data(types, *sem_id, data, &format!("{path}.data"), errors)
+                .map(|data| OwnedData::Data(Box::new(data))),
+            None => {
+                errors.push(IssueFileError::new(path, "missing data"));
+                None
+            }
+        },
+        StateSchema::Attachment(_) => {
+            errors.push(IssueFileError::new(
+                path,
+                "attachments are not supported in issuance files",
+            ));
+            None
+        }
+    };
+    for (key, _) in entry {
+        errors.push(IssueFileError::new(
+            &format!("{path}.{}", value_key(&key)),
+            "unknown field",
+        ));
+    }
+    Some((seal?, state?))
+}
+
+/// Checks the value against its type, making sure it can be serialized.
+fn typed_data(
+    types: &TypeSystem,
+    sem_id: SemId,
+    mut val: Value,
+    path: &str,
+    errors: &mut Vec<IssueFileError>,
+) -> Option<TypedVal> {
+    let count = errors.len();
+    check_value(types, sem_id, &mut val, path, errors, 0);
+    if errors.len() > count {
+        return None;
+    }
+    let result = types
+        .typify(StrictVal::from(val), sem_id)
+        .map_err(|err| err.to_string())
+        .and_then(|typed| match types.strict_serialize_type::<U16>(&typed) {
+            Ok(_) => Ok(typed),
+            Err(err) => Err(err.to_string()),
+        });
+    match result {
+        Ok(data) => Some(data),
+        Err(err) => {
+            errors.push(IssueFileError::new(
+                path,
+                format!("expected {} ({err})", describe(types, sem_id, 0)),
+            ));
+            None
+        }
+    }
+}
+
+/// Checks a value from the issuance file against a strict type, collecting all
+/// the mismatches.
+///
+/// Optional fields missing from structures are set to null, and the fields
+/// are put into the order of the type definition.
+fn check_value(
+    types: &TypeSystem,
+    sem_id: SemId,
+    val: &mut Value,
+    path: &str,
+    errors: &mut Vec<IssueFileError>,
+    depth: usize,
+) {
+    let Some(ty) = types.find(sem_id) else {
+        errors.push(IssueFileError::new(path, format!("unknown type {sem_id}")));
+        return;
+    };
+    if depth > MAX_TYPE_DEPTH * 4 {
+        errors.push(IssueFileError::new(path, "value is nested too deep"));
+        return;
+    }
+    let mismatch = |val: &Value| IssueFileError::mismatch(path, describe(types, sem_id, 0), val);
+    match ty {
+        Ty::Primitive(prim) if *prim == UNIT => {
+            if !val.is_null() {
+                errors.push(mismatch(val));
+            }
+        }
+        Ty::Primitive(prim) => {
+            if !number_fits(*prim, val) {
+                errors.push(mismatch(val));
+            }
+        }
+        Ty::UnicodeChar => {
+            if val.as_str().is_none_or(|s| s.chars().count() != 1) {
+                errors.push(mismatch(val));
+            }
+        }
+        Ty::Enum(variants) => {
+            let known = match val {
+                Value::String(name) => {
+                    FieldName::try_from(name.clone())
+                        .ok()
+                        .and_then(|name| variants.tag_by_name(&name))
+                        .is_some()
+                        || (name.len() == 1 && variants.has_tag(name.as_bytes()[0]))
+                }
+                Value::Number(tag) => tag
+                    .as_u64()
+                    .and_then(|tag| u8::try_from(tag).ok())
+                    .is_some_and(|tag| variants.has_tag(tag)),
+                _ => false,
+            };
+            if !known {
+                errors.push(mismatch(val));
+            }
+        }
+        Ty::Union(variants) if ty.is_option() => {
+            if !val.is_null() {
+                let inner = *variants.ty_by_tag(1).expect("option always has some");
+                check_value(types, inner, val, path, errors, depth + 1);
+            }
+        }
+        Ty::Union(_) => errors.push(IssueFileError::new(
+            path,
+            "union types are not supported in issuance files",
+        )),
+        Ty::Tuple(fields) if fields.len() == 1 => {
+            check_value(types, fields[0], val, path, errors, depth + 1)
+        }
+        Ty::Tuple(fields) => match val {
+            Value::Sequence(items) if items.len() == fields.len() => {
+                for (no, (item, id)) in items.iter_mut().zip(fields.iter()).enumerate() {
+                    check_value(
+                        types,
+                        *id,
+                        item,
+                        &format!("{path}[{no}]"),
+                        errors,
+                        depth + 1,
+                    );
+                }
+            }
+            _ => errors.push(mismatch(val)),
+        },
+        Ty::Struct(fields) => {
+            let Value::Mapping(map) = val else {
+                errors.push(mismatch(val));
+                return;
+            };
+            let mut ordered = Mapping::new();
+            for field in fields.iter() {
+                let field_path = format!("{path}.{}", field.name);
+                match map.remove(field.name.as_str()) {
+                    Some(mut item) => {
+                        check_value(types, field.ty, &mut item, &field_path, errors, depth + 1);
+                        ordered.insert(Value::from(field.name.to_string()), item);
+                    }
+                    None if types.find(field.ty).is_some_and(Ty::is_option) => {
+                        ordered.insert(Value::from(field.name.to_string()), Value::Null);
+                    }
+                    None => errors.push(IssueFileError::new(
+                        &field_path,
+                        format!("missing field of type {}", describe(types, field.ty, 0)),
+                    )),
+                }
+            }
+            for (key, _) in map.iter() {
+                errors.push(IssueFileError::new(
+                    &format!("{path}.{}", value_key(key)),
+                    "unknown field",
+                ));
+            }
+            *map = ordered;
+        }
+        Ty::Array(item, len) => check_collection(
+            types,
+            *item,
+            Sizing::fixed(*len as u64),
+            val,
+            path,
+            errors,
+            depth,
+        ),
+        Ty::List(item, sizing) | Ty::Set(item, sizing) => {
+            check_collection(types, *item, *sizing, val, path, errors, depth)
+        }
+        Ty::Map(key, item, sizing) => {
+            let Value::Mapping(map) = val else {
+                errors.push(mismatch(val));
+                return;
+            };
+            if !sizing.check(map.len()) {
+                errors.push(IssueFileError::new(
+                    path,
+                    format!(
+                        "expected {} entries, found {}",
+                        sizing_str(sizing),
+                        map.len()
+                    ),
+                ));
+            }
+            let entries = std::mem::take(map);
+            for (mut k, mut v) in entries {
+                let entry_path = format!("{path}.{}", value_key(&k));
+                check_value(types, *key, &mut k, &entry_path, errors, depth + 1);
+                check_value(types, *item, &mut v, &entry_path, errors, depth + 1);
+                map.insert(k, v);
+            }
+        }
+    }
+}
+
+fn check_collection(
+    types: &TypeSystem,
+    item: SemId,
+    sizing: Sizing,
+    val: &mut Value,
+    path: &str,
+    errors: &mut Vec<IssueFileError>,
+    depth: usize,
+) {
+    let (len, unit) = match val {
+        Value::String(s) if is_text(types, item) => (s.len(), text_unit(types, item)),
+        Value::Sequence(items) => {
+            for (no, element) in items.iter_mut().enumerate() {
+                check_value(
+                    types,
+                    item,
+                    element,
+                    &format!("{path}[{no}]"),
+                    errors,
+                    depth + 1,
+                );
+            }
+            (items.len(), "items")
+        }
+        _ => {
+            errors.push(IssueFileError::mismatch(
+                path,
+                describe(types, item, 0),
+                val,
+            ));
+            return;
+        }
+    };
+    // Fixed-size arrays may be filled with fewer items
+    if len > sizing.max as usize || (sizing.min != sizing.max && !sizing.check(len)) {
+        errors.push(IssueFileError::new(
+            path,
+            format!("expected {} {unit}, found {len}", sizing_str(&sizing)),
+        ));
+    }
+}
+
+fn number_fits(prim: Primitive, val: &Value) -> bool {
+    let Value::Number(no) = val else {
+        return false;
+    };
+    let info = prim.info();
+    let bits = info.byte_size() as u32 * 8;
+    match info.ty {
+        NumCls::Unsigned | NumCls::NonZero => no.as_u64().is_some_and(|no| {
+            (bits >= 64 || no < 1u64 << bits) && (info.ty != NumCls::NonZero || no != 0)
+        }),
+        NumCls::Signed => no.as_i64().is_some_and(|no| {
+            bits >= 64 || (-(1i64 << (bits - 1))..(1i64 << (bits - 1))).contains(&no)
+        }),
+        NumCls::Float => false,
+    }
+}
+
+/// Detects collections which can be written as a string.
+fn is_text(types: &TypeSystem, item: SemId) -> bool {
+    match types.find(item) {
+        Some(ty) if ty.is_byte() || ty.is_unicode_char() => true,
+        Some(Ty::Enum(variants)) => variants
+            .iter()
+            .all(|variant| (32..=127).contains(&variant.tag)),
+        Some(Ty::Tuple(fields)) if fields.len() == 1 => is_text(types, fields[0]),
+        _ => false,
+    }
+}
+
+fn text_unit(types: &TypeSystem, item: SemId) -> &'static str {
+    match types.find(item) {
+        Some(ty) if ty.is_byte() => "bytes",
+        _ => "characters",
+    }
+}
+
+fn sizing_str(sizing: &Sizing) -> String {
+    match (sizing.min, sizing.max) {
+        (min, max) if min == max => format!("{min}"),
+        (0, max) => format!("up to {max}"),
+        (min, max) => format!("{min} to {max}"),
+    }
+}
+
+/// Human-readable description of a strict type.
+fn describe(types: &TypeSystem, sem_id: SemId, depth: usize) -> String {
+    let Some(ty) = types.find(sem_id) else {
+        return format!("unknown type {sem_id}");
+    };
+    if depth > MAX_TYPE_DEPTH {
+        return "...".to_owned();
+    }
+    match ty {
+        Ty::Primitive(prim) if *prim == UNIT => "null".to_owned(),
+        Ty::Primitive(prim) if ty.is_byte() => format!("byte ({prim})"),
+        Ty::Primitive(prim) => match prim.info().ty {
+            NumCls::Unsigned => format!("unsigned integer ({prim})"),
+            NumCls::Signed => format!("signed integer ({prim})"),
+            NumCls::NonZero => format!("non-zero integer ({prim})"),
+            NumCls::Float => format!("float ({prim})"),
+        },
+        Ty::UnicodeChar => "character".to_owned(),
+        Ty::Enum(variants) if variants.iter().all(|v| (32..=127).contains(&v.tag)) => {
+            "ASCII character".to_owned()
+        }
+        Ty::Enum(variants) => format!(
+            "one of {}",
+            variants
+                .iter()
+                .map(|variant| variant.name.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Ty::Union(variants) if ty.is_option() => {
+            let inner = *variants.ty_by_tag(1).expect("option always has some");
+            format!("optional {}", describe(types, inner, depth + 1))
+        }
+        Ty::Union(variants) => format!(
+            "union of {}",
+            variants
+                .keys()
+                .map(|variant| variant.name.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Ty::Tuple(fields) if fields.len() == 1 => describe(types, fields[0], depth),
+        Ty::Tuple(fields) => format!(
+            "list of ({})",
+            fields
+                .iter()
+                .map(|id| describe(types, *id, depth + 1))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Ty::Struct(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|field| format!("{}: {}", field.name, describe(types, field.ty, depth + 1)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Ty::Array(item, len) if is_text(types, *item) => {
+            format!("string of up to {len} characters")
+        }
+        Ty::List(item, sizing) if is_text(types, *item) => {
+            format!(
+                "string of {} {}",
+                sizing_str(sizing),
+                text_unit(types, *item)
+            )
+        }
+        Ty::Array(item, len) => {
+            format!("list of up to {len} {}", describe(types, *item, depth + 1))
+        }
+        Ty::List(item, sizing) => {
+            format!(
+                "list of {} {}",
+                sizing_str(sizing),
+                describe(types, *item, depth + 1)
+            )
+        }
+        Ty::Set(item, sizing) => format!(
+            "list of {} unique {}",
+            sizing_str(sizing),
+            describe(types, *item, depth + 1)
+        ),
+        Ty::Map(key, item, sizing) => format!(
+            "mapping of {} {} to {}",
+            sizing_str(sizing),
+            describe(types, *key, depth + 1),
+            describe(types, *item, depth + 1)
+        ),
+    }
+}
+
+/// Placeholder value of a strict type, used by the templates.
+fn skeleton(types: &TypeSystem, sem_id: SemId, depth: usize) -> Value {
+    let Some(ty) = types.find(sem_id) else {
+        return Value::Null;
+    };
+    if depth > MAX_TYPE_DEPTH {
+        return Value::Null;
+    }
+    match ty {
+        Ty::Primitive(prim) if *prim == UNIT => Value::Null,
+        Ty::Primitive(_) => Value::from(0u64),
+        Ty::UnicodeChar => Value::from(" "),
+        Ty::Enum(variants) => match variants.iter().next() {
+            Some(variant) if (32..=127).contains(&variant.tag) => {
+                Value::from((variant.tag as char).to_string())
+            }
+            Some(variant) => Value::from(variant.name.to_string()),
+            None => Value::Null,
+        },
+        Ty::Union(_) => Value::Null,
+        Ty::Tuple(fields) if fields.len() == 1 => skeleton(types, fields[0], depth),
+        Ty::Tuple(fields) => Value::Sequence(
+            fields
+                .iter()
+                .map(|id| skeleton(types, *id, depth + 1))
+                .collect(),
+        ),
+        Ty::Struct(fields) => Value::Mapping(
+            fields
+                .iter()
+                .map(|field| {
+                    (
+                        Value::from(field.name.to_string()),
+                        skeleton(types, field.ty, depth + 1),
+                    )
+                })
+                .collect(),
+        ),
+        Ty::Array(item, _) | Ty::List(item, _) | Ty::Set(item, _) if is_text(types, *item) => {
+            Value::from("")
+        }
+        Ty::Array(..) | Ty::List(..) | Ty::Set(..) => Value::Sequence(vec![]),
+        Ty::Map(..) => Value::Mapping(Mapping::new()),
+    }
+}
+
+/// Removes null values, which can't be represented in TOML.
+fn strip_nulls(val: Value) -> Value {
+    match val {
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .filter(|(_, val)| !val.is_null())
+                .map(|(key, val)| (key, strip_nulls(val)))
+                .collect(),
+        ),
+        Value::Sequence(list) => Value::Sequence(list.into_iter().map(strip_nulls).collect()),
+        val => val,
+    }
+}
+
+fn value_key(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim_end()
+            .to_owned(),
+    }
+}
+
+fn find_iface<'stash>(stash: &'stash Stock, name: &str) -> Option<&'stash Iface> {
+    TypeName::try_from(name.to_owned())
+        .ok()
+        .and_then(|name| stash.iface_by_name(&name).ok())
+        .or_else(|| {
+            IfaceId::from_str(name)
+                .ok()
+                .and_then(|id| stash.iface_by_id(id).ok())
+        })
+}
+
+/// Resolves an interface argument, returning the name of the state in the
+/// interface implementation.
+fn arg_spec<'spec>(args: &'spec ArgMap, key: &Value) -> Option<(FieldName, &'spec ArgSpec)> {
+    let (alias, spec) = args
+        .iter()
+        .find(|(alias, _)| key.as_str() == Some(alias.as_str()))?;
+    Some((spec.name.clone().unwrap_or_else(|| alias.clone()), spec))
+}
+
+fn global_sem_id(schema: &SubSchema, iimpl: &IfaceImpl, name: &FieldName) -> Option<SemId> {
+    let info = iimpl.global_state.iter().find(|info| &info.name == name)?;
+    schema
+        .global_types
+        .get(&info.id)
+        .map(|global| global.sem_id)
+}
+
+fn owned_state_schema<'schema>(
+    schema: &'schema SubSchema,
+    iimpl: &IfaceImpl,
+    name: &FieldName,
+) -> Option<&'schema StateSchema> {
+    let info = iimpl.assignments.iter().find(|info| &info.name == name)?;
+    schema.owned_types.get(&info.id)
+}
@@ -1,4 +1,3 @@
-use anyhow::anyhow;
 use bdk::bitcoin::secp256k1::Secp256k1;
 use bdk::bitcoin::bip32::{DerivationPath, ExtendedPrivKey, KeySource};
 use bdk::bitcoin::Network;
@@ -10,6 +9,8 @@ use bdk::miniscript::miniscript;
 use clap::Subcommand;
 use serde_json::json;
 
+use crate::cmds::runtime::RuntimeError;
+
 #[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
 pub enum KeySubCommand {
     /// Generates new random seed mnemonic phrase and corresponding master extended key.
@@ -62,12 +63,14 @@ pub fn handle_key_subcommand(
             };
             let mnemonic: GeneratedKey<_, miniscript::BareCtx> =
                 Mnemonic::generate((mnemonic_type, Language::English))
-                    .map_err(|_| anyhow!("Mnemonic generation error"))?;
+                    .map_err(|_| RuntimeError::Internal("Mnemonic generation error".to_owned()))?;
             let mnemonic = mnemonic.into_key();
             let xkey: ExtendedKey = (mnemonic.clone(), password).into_extended_key()?;
             let xprv = xkey
                 .into_xprv(network)
-                .ok_or_else(|| anyhow!("Privatekey info not found (should not happen)"))?;
+                .ok_or_else(|| {
+                    RuntimeError::Internal("Privatekey info not found (should not happen)".to_owned())
+                })?;
             let fingerprint = xprv.fingerprint(&secp);
             let phrase = mnemonic
                 .word_iter()
@@ -83,7 +86,9 @@ pub fn handle_key_subcommand(
             let xkey: ExtendedKey = (mnemonic, password).into_extended_key()?;
             let xprv = xkey
                 .into_xprv(network)
-                .ok_or_else(|| anyhow!("Privatekey info not found (should not happen)"))?;
+                .ok_or_else(|| {
+                    RuntimeError::Internal("Privatekey info not found (should not happen)".to_owned())
+                })?;
             let fingerprint = xprv.fingerprint(&secp);
 
             Ok(json!({ "xprv": xprv.to_string(), "fingerprint": fingerprint.to_string() }))
@@ -91,7 +96,7 @@ pub fn handle_key_subcommand(
 
         KeySubCommand::Derive { xprv, path } => {
             if xprv.network != network {
-                return Err(anyhow!("InvalidNetwork"));
+                return Err(RuntimeError::usage("InvalidNetwork").into());
             }
             let derived_xprv = &xprv.derive_priv(&secp, &path)?;
 
@@ -104,7 +109,7 @@ pub fn handle_key_subcommand(
                 let desc_pubkey = desc_seckey.to_public(&secp)?;
                 Ok(json!({"xpub": desc_pubkey.to_string(), "xprv": desc_seckey.to_string()}))
             } else {
-                Err(anyhow!("Invalid key variant"))
+                Err(RuntimeError::Internal("Invalid key variant".to_owned()).into())
             }
         }
     }
@@ -1,5 +1,15 @@
-
-
+pub mod backup;
+pub mod batch;
+pub mod history;
+pub mod issue;
 pub mod key;
+pub mod pending;
+pub mod proxy;
+pub mod psbt;
 pub mod rgb;
 pub mod runtime;
+pub mod serve;
+pub mod session;
+pub mod shell;
+pub mod stock;
+pub mod wallet;
@@ -0,0 +1,455 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bdk::bitcoin::{OutPoint, Transaction, Txid};
+use bdk::electrum_client::{self, Client, ElectrumApi, Param};
+use rgb::validation::Validity;
+use rgb_rt::Resolver;
+use rgbstd::containers::{Bindle, Transfer};
+use serde_crate::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::cmds::runtime::RuntimeError;
+use crate::error::ErrorKind;
+use crate::service::{RgbService, ValidationReport};
+
+const PENDING_DIR: &str = "pending";
+const QUEUE_FILE: &str = "queue.yaml";
+
+/// State of a transfer waiting in the queue.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "snake_case")]
+pub enum PendingStatus {
+    /// The witness is not mined deep enough yet.
+    Waiting,
+    /// The witness was mined and is no longer in the chain. The transfer is
+    /// still watched, since the witness may be mined again.
+    Reorged,
+    /// An input of the witness was spent by another transaction, so the
+    /// transfer will never be accepted.
+    DoubleSpent,
+    /// The transfer failed the validation once its witness was mined.
+    Invalid,
+    /// The transfer was added to the stash and removed from the queue.
+    Accepted,
+}
+
+/// Transfer consignment kept in the data directory until its witness
+/// transactions are mined.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct PendingTransfer {
+    pub transfer_id: String,
+    pub contract_id: String,
+    /// Witness transactions of the terminal bundles.
+    pub witnesses: Vec<Txid>,
+    /// Outputs spent by the witnesses, used to detect double spends.
+    #[serde(default)]
+    pub inputs: Vec<OutPoint>,
+    /// Unix time the transfer was queued at.
+    pub queued_at: u64,
+    pub status: PendingStatus,
+    /// Height of the block mining the last of the witnesses.
+    pub height: Option<u32>,
+    pub confirmations: u32,
+    pub message: Option<String>,
+}
+
+/// Transfers waiting for their witnesses, stored in the `pending` directory
+/// next to the stock.
+pub struct PendingQueue {
+    dir: PathBuf,
+    transfers: BTreeMap<String, PendingTransfer>,
+}
+
+impl PendingQueue {
+    pub fn load(service: &RgbService) -> Result<Self, anyhow::Error> {
+        let dir = service
+            .runtime()
+            .stock_path()
+            .parent()
+            .map(|dir| dir.join(PENDING_DIR))
+            .ok_or_else(|| RuntimeError::Custom("stock has no data directory".to_owned()))?;
+        let path = dir.join(QUEUE_FILE);
+        let transfers = if path.exists() {
+            serde_yaml::from_str::<Vec<PendingTransfer>>(&fs::read_to_string(&path)?)?
+                .into_iter()
+                .map(|transfer| (transfer.transfer_id.clone(), transfer))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(PendingQueue { dir, transfers })
+    }
+
+    pub fn store(&self) -> Result<(), anyhow::Error> {
+        fs::create_dir_all(&self.dir)?;
+        let transfers = self.transfers.values().collect::<Vec<_>>();
+        let tmp_path = self.dir.join(QUEUE_FILE).with_extension("yaml.tmp");
+        fs::write(&tmp_path, serde_yaml::to_string(&transfers)?)?;
+        fs::rename(&tmp_path, self.dir.join(QUEUE_FILE))?;
+        Ok(())
+    }
+
+    pub fn transfers(&self) -> impl Iterator<Item = &PendingTransfer> {
+        self.transfers.values()
+    }
+
+    fn consignment_path(&self, transfer_id: &str) -> PathBuf {
+        // Transfer ids have a `urn:` like prefix which is not a valid file
+        // name on all platforms.
+        let name = transfer_id.rsplit(':').next().unwrap_or(transfer_id);
+        self.dir.join(format!("{name}.rgb"))
+    }
+}
+
+/// Validates a transfer and queues it until its witnesses are mined.
+///
+/// Transfers which are valid apart from their non-mined terminal witnesses
+/// are queued; any other validation failure is returned as an error.
+pub fn enqueue(
+    service: &mut RgbService,
+    bindle: Bindle<Transfer>,
+) -> Result<PendingTransfer, anyhow::Error> {
+    let transfer = bindle.clone().unbindle();
+    service.resolver.add_terminals(&transfer);
+    let witnesses = witness_txids(&transfer)?;
+    let inputs = transfer
+        .terminals
+        .values()
+        .filter_map(|terminal| terminal.as_reduced_unsafe().tx.as_ref())
+        .flat_map(|tx| tx.inputs.iter())
+        .map(|input| {
+            Txid::from_str(&input.prev_output.txid.to_string())
+                .map(|txid| OutPoint::new(txid, input.prev_output.vout.into_u32()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if witnesses.is_empty() {
+        return Err(RuntimeError::usage("the transfer has no terminal witness to watch").into());
+    }
+
+    let transfer = match transfer.validate(&mut service.resolver, true) {
+        Ok(transfer) => transfer,
+        Err(transfer)
+            if transfer
+                .validation_status()
+                .is_some_and(|status| status.validity() == Validity::UnminedTerminals) =>
+        {
+            transfer
+        }
+        Err(transfer) => return Err(RuntimeError::validation(transfer).into()),
+    };
+
+    let mut queue = PendingQueue::load(service)?;
+    let transfer_id = transfer.transfer_id().to_string();
+    let pending = PendingTransfer {
+        transfer_id: transfer_id.clone(),
+        contract_id: transfer.contract_id().to_string(),
+        witnesses,
+        inputs,
+        queued_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default(),
+        status: PendingStatus::Waiting,
+        height: None,
+        confirmations: 0,
+        message: None,
+    };
+    fs::create_dir_all(&queue.dir)?;
+    bindle.save(queue.consignment_path(&transfer_id))?;
+    queue.transfers.insert(transfer_id, pending.clone());
+    queue.store()?;
+    Ok(pending)
+}
+
+/// Witness transactions of the terminal bundles of a transfer.
+pub fn witness_txids(transfer: &Transfer) -> Result<Vec<Txid>, anyhow::Error> {
+    Ok(transfer
+        .terminals
+        .keys()
+        .filter_map(|bundle_id| transfer.anchored_bundle(*bundle_id))
+        .filter_map(|anchored| anchored.anchor.witness_id())
+        .map(|witness| Txid::from_str(&witness.as_reduced_unsafe().to_string()))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Position of a witness transaction as seen by the Electrum server
+enum WitnessStatus {
+    Mined(u32),
+    Mempool,
+    /// Neither mined nor in the mempool of the server.
+    Missing,
+    DoubleSpent(Txid),
+}
+
+/// Checks the witnesses of all watched transfers once, accepting the
+/// transfers whose witnesses are mined at least `depth` blocks deep.
+///
+/// Returns the transfers of the queue with their updated status, including
+/// the ones accepted during this round.
+pub fn watch_once(
+    service: &mut RgbService,
+    electrum: &str,
+    esplora: &str,
+    depth: u32,
+) -> Result<Vec<PendingTransfer>, anyhow::Error> {
+    let mut queue = PendingQueue::load(service)?;
+    let client = Client::new(electrum).map_err(RuntimeError::network)?;
+    let tip = client
+        .block_headers_subscribe()
+        .map_err(RuntimeError::network)?
+        .height as u32;
+
+    let mut report = vec![];
+    for transfer_id in queue.transfers.keys().cloned().collect::<Vec<_>>() {
+        let mut pending = queue.transfers[&transfer_id].clone();
+        if matches!(
+            pending.status,
+            PendingStatus::DoubleSpent | PendingStatus::Invalid
+        ) {
+            report.push(pending);
+            continue;
+        }
+        check_witnesses(&client, tip, &mut pending)?;
+
+        if pending.status == PendingStatus::Waiting
+            && pending.height.is_some()
+            && pending.confirmations >= depth
+        {
+            let path = queue.consignment_path(&transfer_id);
+            match accept_mined(service, esplora, &path) {
+                Ok(_) => {
+                    eprintln!(
+                        "Transfer {transfer_id} accepted with {} confirmations",
+                        pending.confirmations
+                    );
+                    pending.status = PendingStatus::Accepted;
+                    pending.message = None;
+                    queue.transfers.remove(&transfer_id);
+                    fs::remove_file(&path)?;
+                    queue.store()?;
+                    report.push(pending);
+                    continue;
+                }
+                // The indexer is not reachable, the next round will retry
+                Err(err) if err.kind() == ErrorKind::Network => {
+                    pending.message = Some(err.to_string())
+                }
+                Err(err) => {
+                    eprintln!("Transfer {transfer_id} is not valid: {err}");
+                    pending.status = PendingStatus::Invalid;
+                    pending.message = Some(err.to_string());
+                }
+            }
+        }
+
+        queue.transfers.insert(transfer_id, pending.clone());
+        queue.store()?;
+        report.push(pending);
+    }
+    Ok(report)
+}
+
+/// Updates the confirmations and the status of a transfer from the positions
+/// of its witnesses.
+fn check_witnesses(
+    client: &Client,
+    tip: u32,
+    pending: &mut PendingTransfer,
+) -> Result<(), RuntimeError> {
+    let mut height = Some(0);
+    for witness in pending.witnesses.clone() {
+        match witness_status(client, tip, witness, pending)? {
+            WitnessStatus::Mined(mined) => height = height.map(|height: u32| height.max(mined)),
+            WitnessStatus::Mempool | WitnessStatus::Missing => height = None,
+            WitnessStatus::DoubleSpent(spent_by) => {
+                eprintln!(
+                    "Transfer {} is double-spent: witness {witness} conflicts with {spent_by}",
+                    pending.transfer_id
+                );
+                pending.status = PendingStatus::DoubleSpent;
+                pending.confirmations = 0;
+                pending.height = None;
+                pending.message = Some(format!(
+                    "witness {witness} conflicts with transaction {spent_by}"
+                ));
+                return Ok(());
+            }
+        }
+    }
+
+    match (pending.height, height) {
+        (Some(previous), None) => {
+            eprintln!(
+                "Transfer {} was reorged out: its witness mined at height {previous} is no \
+                 longer in the chain",
+                pending.transfer_id
+            );
+            pending.status = PendingStatus::Reorged;
+            pending.message = Some(format!(
+                "witness mined at height {previous} is no longer in the chain"
+            ));
+        }
+        (_, Some(_)) => {
+            pending.status = PendingStatus::Waiting;
+            pending.message = None;
+        }
+        (None, None) => {}
+    }
+    pending.height = height;
+    pending.confirmations = height.map_or(0, |height| tip.saturating_sub(height) + 1);
+    Ok(())
+}
+
+fn witness_status(
+    client: &Client,
+    tip: u32,
+    txid: Txid,
+    pending: &mut PendingTransfer,
+) -> Result<WitnessStatus, RuntimeError> {
+    let tx = match client.transaction_get(&txid) {
+        Ok(tx) => tx,
+        Err(err) if is_unknown_tx(&err) => {
+            return find_conflict(client, txid, &pending.inputs);
+        }
+        Err(err) => return Err(RuntimeError::network(err)),
+    };
+    for input in &tx.input {
+        if !pending.inputs.contains(&input.previous_output) {
+            pending.inputs.push(input.previous_output);
+        }
+    }
+    match witness_height(client, tip, &tx)? {
+        Some(0) => Ok(WitnessStatus::Mempool),
+        Some(height) => Ok(WitnessStatus::Mined(height)),
+        None => find_conflict(client, txid, &pending.inputs),
+    }
+}
+
+/// Height at which a known witness is mined, `Some(0)` when it is in the
+/// mempool and `None` when the server doesn't index it anymore.
+///
+/// Servers backed by a full node report the confirmations of a verbose
+/// transaction. Others only index scripts, so the witness is looked up in the
+/// history of one of its spendable outputs, or of the first output it spends
+/// when all its outputs are `OP_RETURN` ones.
+fn witness_height(
+    client: &Client,
+    tip: u32,
+    tx: &Transaction,
+) -> Result<Option<u32>, RuntimeError> {
+    let txid = tx.txid();
+    let params = [Param::String(txid.to_string()), Param::Bool(true)];
+    match client.raw_call("blockchain.transaction.get", params) {
+        Ok(verbose) => {
+            let confirmations = verbose
+                .get("confirmations")
+                .and_then(JsonValue::as_u64)
+                .unwrap_or_default() as u32;
+            return Ok(Some(match confirmations {
+                0 => 0,
+                confirmations => tip.saturating_sub(confirmations - 1),
+            }));
+        }
+        // Verbose transactions are not supported by the server
+        Err(electrum_client::Error::Protocol(_)) => {}
+        Err(err) => return Err(RuntimeError::network(err)),
+    }
+
+    let script = match tx
+        .output
+        .iter()
+        .find(|output| !output.script_pubkey.is_op_return())
+    {
+        Some(output) => output.script_pubkey.clone(),
+        None => {
+            let Some(input) = tx.input.first() else {
+                return Ok(None);
+            };
+            let prev_tx = client
+                .transaction_get(&input.previous_output.txid)
+                .map_err(RuntimeError::network)?;
+            let Some(prev_out) = prev_tx.output.get(input.previous_output.vout as usize) else {
+                return Ok(None);
+            };
+            prev_out.script_pubkey.clone()
+        }
+    };
+    let history = client
+        .script_get_history(&script)
+        .map_err(RuntimeError::network)?;
+    Ok(history
+        .into_iter()
+        .find(|item| item.tx_hash == txid)
+        .map(|item| item.height.max(0) as u32))
+}
+
+/// Whether the server failed because it doesn't know the transaction.
+///
+/// Servers either forward the error of the full node (code -5, "No such
+/// mempool or blockchain transaction") or report it in their own words.
+fn is_unknown_tx(err: &electrum_client::Error) -> bool {
+    let electrum_client::Error::Protocol(value) = err else {
+        return false;
+    };
+    let message = value.to_string().to_lowercase();
+    message.contains("no such mempool or blockchain transaction")
+        || message.contains("transaction not found")
+        || message.contains("missing transaction")
+}
+
+/// Looks for a transaction other than the witness spending one of its inputs.
+fn find_conflict(
+    client: &Client,
+    txid: Txid,
+    inputs: &[OutPoint],
+) -> Result<WitnessStatus, RuntimeError> {
+    for input in inputs {
+        let prev_tx = client
+            .transaction_get(&input.txid)
+            .map_err(RuntimeError::network)?;
+        let Some(prev_out) = prev_tx.output.get(input.vout as usize) else {
+            continue;
+        };
+        let history = client
+            .script_get_history(&prev_out.script_pubkey)
+            .map_err(RuntimeError::network)?;
+        for item in history.into_iter().filter(|item| item.tx_hash != txid) {
+            let tx = client
+                .transaction_get(&item.tx_hash)
+                .map_err(RuntimeError::network)?;
+            if tx.input.iter().any(|spent| spent.previous_output == *input) {
+                return Ok(WitnessStatus::DoubleSpent(item.tx_hash));
+            }
+        }
+    }
+    Ok(WitnessStatus::Missing)
+}
+
+/// Accepts a queued transfer whose witnesses are mined.
+///
+/// The validation uses a new resolver, since the one of the service reports
+/// the terminal witnesses cached from the consignments as not mined.
+fn accept_mined(
+    service: &mut RgbService,
+    esplora: &str,
+    path: &Path,
+) -> Result<ValidationReport, RuntimeError> {
+    let transfer = Bindle::<Transfer>::load_file(path)
+        .map_err(RuntimeError::from)?
+        .unbindle();
+    let mut resolver = Resolver::new(esplora).map_err(RuntimeError::network)?;
+    let transfer = transfer
+        .validate(&mut resolver, true)
+        .map_err(RuntimeError::validation)?;
+    let report = ValidationReport::new(transfer.contract_id(), transfer.validation_status());
+    service
+        .runtime
+        .accept_transfer(transfer, &mut resolver, false)?;
+    service.runtime.store()?;
+    Ok(report)
+}
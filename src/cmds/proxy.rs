@@ -0,0 +1,365 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bdk::bitcoin::Txid;
+use bpstd::Address;
+use rand::RngCore;
+use rgb::validation::Validity;
+use rgbstd::containers::{Bindle, Transfer};
+use rgbstd::invoice::{Beneficiary, RgbInvoice, RgbTransport};
+use serde_crate::Deserialize;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::pending::{enqueue, witness_txids};
+use crate::cmds::runtime::RuntimeError;
+use crate::error::ErrorKind;
+use crate::service::RgbService;
+
+const ACK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Parses a proxy given as an invoice transport, like `rpcs://host/path`, or
+/// as an HTTP URL, like `https://host/path`.
+pub fn parse_proxy(proxy: &str) -> Result<RgbTransport, RuntimeError> {
+    match RgbTransport::from_str(proxy) {
+        Ok(RgbTransport::JsonRpc { tls, host }) | Ok(RgbTransport::RestHttp { tls, host }) => {
+            Ok(RgbTransport::JsonRpc { tls, host })
+        }
+        _ => Err(RuntimeError::usage(format!(
+            "invalid proxy '{proxy}', expected rpc://, rpcs://, http:// or https:// URL"
+        ))),
+    }
+}
+
+/// Identifier the consignments paying an invoice are kept under by the proxy:
+/// the blinded seal or the address of the beneficiary.
+pub fn recipient_id(invoice: &RgbInvoice) -> String {
+    match invoice.beneficiary.into_inner() {
+        Beneficiary::BlindedSeal(seal) => seal.to_string(),
+        Beneficiary::WitnessVout(payload) => {
+            Address::new(payload, invoice.address_network()).to_string()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RpcResponse {
+    #[serde(default)]
+    result: JsonValue,
+    error: Option<RpcError>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ProxyConsignment {
+    /// Base64 encoded consignment file.
+    consignment: String,
+}
+
+/// Client of an RGB proxy, the JSON-RPC server relaying the consignments and
+/// their ACKs between the payer and the payee.
+pub struct ProxyClient {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl ProxyClient {
+    /// Connects to the proxy given on the command line or, if there is none,
+    /// to the first JSON-RPC transport of the invoice.
+    pub fn for_invoice(proxy: Option<&str>, invoice: &RgbInvoice) -> Result<Self, RuntimeError> {
+        let transport = match proxy {
+            Some(proxy) => parse_proxy(proxy)?,
+            None => invoice
+                .transports
+                .iter()
+                .find(|transport| matches!(transport, RgbTransport::JsonRpc { .. }))
+                .cloned()
+                .ok_or_else(|| {
+                    RuntimeError::usage(
+                        "the invoice has no RGB proxy transport, please provide --proxy",
+                    )
+                })?,
+        };
+        let RgbTransport::JsonRpc { tls, host } = transport else {
+            unreachable!("only JSON-RPC transports are selected")
+        };
+        let scheme = if tls { "https" } else { "http" };
+        Ok(ProxyClient {
+            url: format!("{scheme}://{host}"),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Uploads a consignment file, which the proxy keeps under the recipient
+    /// id until the payee fetches it. Fails unless the proxy confirms the
+    /// upload.
+    pub fn post_consignment(
+        &self,
+        recipient_id: &str,
+        txid: Txid,
+        consignment: &[u8],
+    ) -> Result<(), RuntimeError> {
+        check_form_value(recipient_id)?;
+        let boundary = format!("bihelix-rgb-cli-{:016x}", rand::thread_rng().next_u64());
+        let mut body = Vec::new();
+        for (name, value) in [
+            ("jsonrpc", "2.0"),
+            ("id", "1"),
+            ("method", "consignment.post"),
+            ("params[recipient_id]", recipient_id),
+            ("params[txid]", &txid.to_string()),
+        ] {
+            body.extend(
+                format!(
+                    "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n\
+                     {value}\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; \
+                 filename=\"consignment.rgb\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend(consignment);
+        body.extend(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let response = self
+            .agent
+            .post(&self.url)
+            .set(
+                "Content-Type",
+                &format!("multipart/form-data; boundary={boundary}"),
+            )
+            .send_bytes(&body);
+        match self.result(response)?.as_bool() {
+            Some(true) => Ok(()),
+            _ => Err(RuntimeError::network(format!(
+                "the proxy refused the consignment for {recipient_id}"
+            ))),
+        }
+    }
+
+    /// Downloads the consignment kept under the recipient id.
+    pub fn get_consignment(&self, recipient_id: &str) -> Result<Vec<u8>, RuntimeError> {
+        let result = self.call("consignment.get", json!({ "recipient_id": recipient_id }))?;
+        if result.is_null() {
+            return Err(RuntimeError::usage(format!(
+                "the proxy has no consignment for {recipient_id}"
+            )));
+        }
+        let consignment = serde_json::from_value::<ProxyConsignment>(result)
+            .map_err(|err| RuntimeError::network(format!("invalid proxy response: {err}")))?;
+        base64::decode(consignment.consignment)
+            .map_err(|err| RuntimeError::network(format!("invalid consignment encoding: {err}")))
+    }
+
+    /// Tells the payer whether the consignment was accepted (ACK) or refused
+    /// as invalid (NACK).
+    pub fn post_ack(&self, recipient_id: &str, ack: bool) -> Result<(), RuntimeError> {
+        self.call(
+            "ack.post",
+            json!({ "recipient_id": recipient_id, "ack": ack }),
+        )?;
+        Ok(())
+    }
+
+    /// ACK or NACK posted by the payee, if any yet.
+    pub fn get_ack(&self, recipient_id: &str) -> Result<Option<bool>, RuntimeError> {
+        let result = self.call("ack.get", json!({ "recipient_id": recipient_id }))?;
+        Ok(result.as_bool())
+    }
+
+    fn call(&self, method: &str, params: JsonValue) -> Result<JsonValue, RuntimeError> {
+        log::debug!("RGB proxy request {method} to {}", self.url);
+        let response = self.agent.post(&self.url).send_json(json!({
+            "jsonrpc": "2.0",
+            "id": "1",
+            "method": method,
+            "params": params,
+        }));
+        self.result(response)
+    }
+
+    fn result(
+        &self,
+        response: Result<ureq::Response, ureq::Error>,
+    ) -> Result<JsonValue, RuntimeError> {
+        let response = match response {
+            Ok(response) => response,
+            // JSON-RPC errors may come with an HTTP error status
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(RuntimeError::network(format!("RGB proxy: {err}"))),
+        };
+        let body = response
+            .into_string()
+            .map_err(|err| RuntimeError::network(format!("RGB proxy {}: {err}", self.url)))?;
+        let response = serde_json::from_str::<RpcResponse>(&body)
+            .map_err(|err| RuntimeError::network(format!("invalid proxy response: {err}")))?;
+        match response.error {
+            Some(err) => Err(RuntimeError::network(format!(
+                "RGB proxy error {}: {}",
+                err.code, err.message
+            ))),
+            None => Ok(response.result),
+        }
+    }
+}
+
+/// Checks that a value can be put into a multipart form field as is.
+///
+/// Recipient ids are blinded seals or addresses, so anything besides
+/// alphanumerics and the separators they use would break the form.
+fn check_form_value(value: &str) -> Result<(), RuntimeError> {
+    if value.is_empty()
+        || !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'))
+    {
+        return Err(RuntimeError::usage(format!(
+            "invalid recipient id '{value}' for the RGB proxy"
+        )));
+    }
+    Ok(())
+}
+
+/// Posts a transfer consignment paying an invoice to the RGB proxy and,
+/// with `wait`, polls the proxy until the payee posts an ACK or NACK, for at
+/// most `timeout`.
+pub fn send(
+    invoice: &RgbInvoice,
+    proxy: Option<&str>,
+    file: &Path,
+    wait: bool,
+    timeout: Duration,
+) -> Result<JsonValue, anyhow::Error> {
+    let client = ProxyClient::for_invoice(proxy, invoice)?;
+    let recipient_id = recipient_id(invoice);
+    let data = fs::read(file)?;
+    let transfer = Bindle::<Transfer>::load(data.as_slice())
+        .map_err(RuntimeError::from)?
+        .unbindle();
+    let txid = *witness_txids(&transfer)?
+        .first()
+        .ok_or_else(|| RuntimeError::usage("the transfer has no terminal witness"))?;
+
+    client.post_consignment(&recipient_id, txid, &data)?;
+    eprintln!("Consignment posted to {} for {recipient_id}", client.url());
+
+    let started = Instant::now();
+    let mut ack = client.get_ack(&recipient_id)?;
+    while wait && ack.is_none() {
+        if started.elapsed() >= timeout {
+            return Err(RuntimeError::network(format!(
+                "no ACK or NACK from the payee within {}s",
+                timeout.as_secs()
+            ))
+            .into());
+        }
+        thread::sleep(ACK_POLL_INTERVAL.min(timeout.saturating_sub(started.elapsed())));
+        ack = client.get_ack(&recipient_id)?;
+    }
+    match ack {
+        Some(true) => eprintln!("The payee accepted the consignment"),
+        Some(false) => eprintln!("The payee refused the consignment"),
+        None => {}
+    }
+    Ok(json!({
+        "recipient_id": recipient_id,
+        "proxy": client.url(),
+        "txid": txid,
+        "ack": ack,
+    }))
+}
+
+/// Fetches the consignment paying an invoice from the RGB proxy, validates
+/// and accepts it, and posts an ACK back, or a NACK if it is not valid.
+///
+/// With `queue`, a transfer whose witness is not mined yet is queued for
+/// `rgb watch` and acknowledged. Without it such a transfer is left on the
+/// proxy, so it can be received again once the witness is mined.
+pub fn receive(
+    service: &mut RgbService,
+    invoice: &RgbInvoice,
+    proxy: Option<&str>,
+    queue: bool,
+    out: Option<&Path>,
+) -> Result<JsonValue, anyhow::Error> {
+    let client = ProxyClient::for_invoice(proxy, invoice)?;
+    let recipient_id = recipient_id(invoice);
+    let data = client.get_consignment(&recipient_id)?;
+    if let Some(out) = out {
+        fs::write(out, &data)?;
+    }
+    let nack = |err: RuntimeError| -> Result<JsonValue, anyhow::Error> {
+        if err.kind() == ErrorKind::InvalidConsignment {
+            client.post_ack(&recipient_id, false)?;
+            eprintln!("NACK posted, the consignment is not valid");
+        }
+        Err(err.into())
+    };
+    let bindle = match Bindle::<Transfer>::load(data.as_slice()) {
+        Ok(bindle) => bindle,
+        Err(err) => return nack(RuntimeError::MalformedConsignment(err)),
+    };
+
+    if queue {
+        let pending = match enqueue(service, bindle) {
+            Ok(pending) => pending,
+            Err(err) => return nack(RuntimeError::classify(err)),
+        };
+        client.post_ack(&recipient_id, true)?;
+        eprintln!("Transfer queued and ACK posted");
+        return Ok(json!({
+            "recipient_id": recipient_id,
+            "proxy": client.url(),
+            "ack": true,
+            "pending": pending,
+        }));
+    }
+
+    let report = match service.accept(bindle.unbindle(), false) {
+        Ok(report) => report,
+        Err(err) => match RuntimeError::classify(err) {
+            RuntimeError::InvalidConsignment(status)
+                if status.validity() == Validity::UnminedTerminals =>
+            {
+                return Err(RuntimeError::usage(
+                    "the witness of the transfer is not mined yet, receive it with --queue to \
+                     accept it once mined",
+                )
+                .into())
+            }
+            err => return nack(err),
+        },
+    };
+    client.post_ack(&recipient_id, true)?;
+    eprintln!("Transfer accepted into the stash and ACK posted");
+    Ok(json!({
+        "recipient_id": recipient_id,
+        "proxy": client.url(),
+        "ack": true,
+        "contract_id": report.contract_id.to_string(),
+        "validity": report.validity,
+        "status": report.status,
+    }))
+}
@@ -0,0 +1,175 @@
+use amplify::hex::ToHex;
+use amplify::Wrapper;
+use bdk::bitcoin::script::Instruction;
+use bdk::bitcoin::{Address, Network};
+use psbt::{RgbExt, RgbInExt, RgbOutExt};
+use rgbstd::Operation;
+use serde_json::{json, Value as JsonValue};
+
+use crate::fee::{fee_report, InputType, TxWeight};
+use crate::utils::{decode_psbt, is_final, psbt_input_txout};
+
+/// Summarizes a PSBT for review before signing.
+///
+/// Bitcoin-level data is read from the PSBT itself, while the RGB proprietary
+/// keys written by `rgb sethost` and the `rgb-psbt` crate are decoded from the
+/// same data using the LNP/BP PSBT implementation.
+pub fn analyze_psbt(data: &[u8], network: Network) -> Result<JsonValue, anyhow::Error> {
+    let (psbt, version) = decode_psbt(data)?;
+    let rgb_psbt = psbt::Psbt::deserialize(data)?;
+    let contract_ids = rgb_psbt.rgb_contract_ids()?;
+
+    let mut input_sum = Some(0u64);
+    let mut weight = TxWeight::new();
+    let mut weight_known = true;
+    let mut inputs = vec![];
+    for (index, (txin, input)) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs).enumerate() {
+        let txout = psbt_input_txout(&psbt, index);
+        input_sum = input_sum
+            .zip(txout.as_ref())
+            .map(|(sum, txout)| sum + txout.value);
+        match InputType::from_psbt_input(&psbt, index) {
+            Some(input_type) => {
+                weight.add_input(input_type.to_input_weight());
+            }
+            None => weight_known = false,
+        }
+
+        let signatures = if input.final_script_sig.is_some() || input.final_script_witness.is_some()
+        {
+            "finalized"
+        } else if !input.partial_sigs.is_empty()
+            || input.tap_key_sig.is_some()
+            || !input.tap_script_sigs.is_empty()
+        {
+            "signed"
+        } else {
+            "unsigned"
+        };
+
+        let mut rgb_consumers = vec![];
+        if let Some(rgb_input) = rgb_psbt.input(index) {
+            for contract_id in &contract_ids {
+                if let Some(opid) = rgb_input.rgb_consumer(*contract_id)? {
+                    rgb_consumers.push(json!({
+                        "contract_id": contract_id.to_string(),
+                        "opid": opid.to_string(),
+                    }));
+                }
+            }
+        }
+
+        inputs.push(json!({
+            "outpoint": txin.previous_output,
+            "value": txout.as_ref().map(|txout| txout.value),
+            "address": txout.as_ref().and_then(|txout| {
+                Address::from_script(&txout.script_pubkey, network).ok()
+            }),
+            "sequence": txin.sequence.to_consensus_u32(),
+            "sighash": input.sighash_type.map(|sighash| sighash.to_string()),
+            "signatures": signatures,
+            "partial_sigs": input.partial_sigs.len() + input.tap_script_sigs.len()
+                + input.tap_key_sig.iter().count(),
+            "rgb_consumed_by": rgb_consumers,
+        }));
+    }
+
+    let mut outputs = vec![];
+    for (index, txout) in psbt.unsigned_tx.output.iter().enumerate() {
+        weight.add_output(txout.script_pubkey.len());
+        let mut output = json!({
+            "vout": index,
+            "value": txout.value,
+        });
+        if txout.script_pubkey.is_op_return() {
+            let data = txout
+                .script_pubkey
+                .instructions()
+                .filter_map(|instruction| match instruction {
+                    Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_hex()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            output["op_return"] = json!(data);
+        } else {
+            output["address"] = json!(Address::from_script(&txout.script_pubkey, network)
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| txout.script_pubkey.to_hex_string()));
+        }
+        if let Some(rgb_output) = rgb_psbt.output(index) {
+            let mpc_messages = rgb_output
+                .mpc_message_map()
+                .map(|map| {
+                    map.iter()
+                        .map(|(protocol_id, message)| {
+                            (protocol_id.to_string(), json!(message.to_string()))
+                        })
+                        .collect::<serde_json::Map<_, _>>()
+                })
+                .unwrap_or_default();
+            output["rgb"] = json!({
+                "opret_host": rgb_output.is_opret_host(),
+                "tapret_host": rgb_output.is_tapret_host(),
+                "velocity_hint": rgb_output.rgb_velocity_hint().map(|hint| hint.to_string()),
+                "mpc_messages": mpc_messages,
+                "opret_commitment": rgb_output
+                    .opret_commitment()
+                    .ok()
+                    .map(|commitment| commitment.to_string()),
+                "tapret_commitment": rgb_output
+                    .tapret_commitment()
+                    .ok()
+                    .map(|commitment| commitment.to_string()),
+            });
+        }
+        outputs.push(output);
+    }
+
+    let mut transitions = vec![];
+    for contract_id in &contract_ids {
+        for opid in rgb_psbt.rgb_op_ids(*contract_id)? {
+            let transition = rgb_psbt.rgb_transition(opid)?;
+            transitions.push(json!({
+                "contract_id": contract_id.to_string(),
+                "opid": opid.to_string(),
+                "known": transition.is_some(),
+                "transition_type": transition.as_ref().map(|t| t.transition_type.to_inner()),
+                "inputs": transition.as_ref().map(|t| t.inputs().len()),
+                "close_methods": rgb_psbt.rgb_close_methods(opid)?,
+            }));
+        }
+    }
+
+    let output_sum = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| txout.value)
+        .sum::<u64>();
+    let fee = input_sum.and_then(|sum| sum.checked_sub(output_sum));
+    let vsize = if is_final(&psbt).is_ok() {
+        Some(psbt.clone().extract_tx().vsize() as u64)
+    } else if weight_known {
+        Some(weight.vsize())
+    } else {
+        None
+    };
+
+    Ok(json!({
+        "psbt_version": version.to_string(),
+        "txid": psbt.unsigned_tx.txid(),
+        "version": psbt.unsigned_tx.version,
+        "lock_time": psbt.unsigned_tx.lock_time.to_consensus_u32(),
+        "inputs": inputs,
+        "outputs": outputs,
+        // The fee rate needs the size, which can't be estimated for some inputs
+        "fee": fee.map(|fee| match vsize {
+            Some(vsize) => fee_report(fee, vsize),
+            None => json!({ "fee": fee, "vsize": null, "fee_rate": null }),
+        }),
+        "rgb": {
+            "contract_ids": contract_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+            "transitions": transitions,
+        },
+    }))
+}
@@ -1,41 +1,45 @@
-use std::collections::HashSet;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use amplify::hex::ToHex;
-use amplify::Array;
-use amplify::{
-    confinement::{U16, U24},
-    Display,
-};
+use amplify::Wrapper;
+use amplify::{confinement::U24, Display};
 use bdk::bitcoin::Network;
+use bdk::blockchain::ElectrumBlockchain;
+use bdk::database::MemoryDatabase;
 use bdk::electrum_client::ElectrumApi;
+use bdk::SyncOptions;
 use bp::{Outpoint, Vout};
-use psbt::{Psbt, PsbtVer, RgbOutExt};
-use seals::txout::SealTxid;
+use psbt::{Psbt, PsbtVer, RgbOutExt, RgbPsbt};
 
-use crate::cmds::runtime::Runtime;
-use anyhow::anyhow;
+use crate::cmds::history::{contract_history, Ownership};
+use crate::cmds::issue::{
+    issue_from_file, issue_rgb20, issue_rgb21, issue_rgb25, issue_template, IssueFormat, IssueOpts,
+};
+use crate::cmds::pending::{enqueue, watch_once};
+use crate::cmds::proxy::{receive, send};
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::stock::{check_stock, merge_stock, reindex_stock, OfflineResolver};
+use crate::service::{ConsignmentValidity, Imported, InvoiceRequest, RgbService, StateRequest};
 use bp::seals::txout::CloseMethod;
 use clap::{Subcommand, ValueEnum};
-use rgb::validation::Validity;
 use rgb::BlindingFactor;
-use rgb::OutputSeal;
-use rgb_rt::{Resolver, RuntimeError};
-use rgb_schemata::{nia_rgb20, nia_schema};
-use rgbstd::containers::{Bindle, BindleContent, BuilderSeal, Transfer, UniversalBindle};
-use rgbstd::contract::{ContractId, GenesisSeal, GraphSeal, StateType};
-use rgbstd::interface::{ContractBuilder, FilterExclude, IfaceId, SchemaIfaces};
-use rgbstd::invoice::{Beneficiary, RgbInvoiceBuilder, XChainNet};
-use rgbstd::persistence::{Inventory, Stash};
+use rgb_schemata::{cfa_rgb25, cfa_schema, nia_rgb20, nia_schema, uda_rgb21, uda_schema};
+use rgbfs::StockFs;
+use rgbstd::containers::{
+    Batch, Bindle, BindleContent, BuilderSeal, Contract, Transfer, TransitionInfo, UniversalBindle,
+};
+use rgbstd::contract::{ContractId, GraphSeal};
+use rgbstd::interface::{rgb20, rgb21, rgb25, TypedState};
+use rgbstd::invoice::RgbInvoice;
+use rgbstd::persistence::{Inventory, Stash, Stock};
 use rgbstd::schema::SchemaId;
-use rgbstd::XChain;
-use serde_json::Value::Null as JsonNull;
+use rgbstd::{Operation, SecretSeal, XChain, XOutpoint};
+use serde_json::json;
+use serde_json::Value as JsonValue;
 use strict_types::encoding::{FieldName, StrictSerialize, TypeName};
-use strict_types::tn;
-use strict_types::StrictVal;
 
 /// Rgb operation subcommands
 
@@ -50,6 +54,99 @@ pub enum InspectFormat {
     Contractum,
 }
 
+/// Seal receiving the state assigned by a state transition
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display)]
+pub enum TransitionSeal {
+    /// Output of the witness transaction, i.e. the PSBT the transition is added to
+    #[display("{0}")]
+    Vout(Vout),
+    /// Existing transaction output
+    #[display("{0}")]
+    Outpoint(Outpoint),
+    /// Blinded seal provided by the receiver in an invoice
+    #[display("{0}")]
+    Blinded(SecretSeal),
+}
+
+impl FromStr for TransitionSeal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(vout) = u32::from_str(s) {
+            Ok(TransitionSeal::Vout(Vout::from_u32(vout)))
+        } else if let Ok(outpoint) = Outpoint::from_str(s) {
+            Ok(TransitionSeal::Outpoint(outpoint))
+        } else {
+            SecretSeal::from_str(s)
+                .map(TransitionSeal::Blinded)
+                .map_err(|_| format!("invalid seal '{s}', expected VOUT, TXID:VOUT or utxob:..."))
+        }
+    }
+}
+
+impl TransitionSeal {
+    fn to_builder_seal(self, method: CloseMethod) -> BuilderSeal<GraphSeal> {
+        match self {
+            TransitionSeal::Vout(vout) => {
+                BuilderSeal::Revealed(XChain::Bitcoin(GraphSeal::new_random_vout(method, vout)))
+            }
+            TransitionSeal::Outpoint(outpoint) => BuilderSeal::Revealed(XChain::Bitcoin(
+                GraphSeal::new_random(method, outpoint.txid, outpoint.vout),
+            )),
+            TransitionSeal::Blinded(secret) => BuilderSeal::Concealed(XChain::Bitcoin(secret)),
+        }
+    }
+}
+
+/// Parse a transition output (Seal=Amount) argument from cli input.
+fn parse_transition_output(s: &str) -> Result<(TransitionSeal, u64), String> {
+    let (seal, amount) = s
+        .rsplit_once('=')
+        .ok_or_else(|| "invalid format, expected SEAL=AMOUNT".to_string())?;
+    let amount = u64::from_str(amount).map_err(|e| e.to_string())?;
+    Ok((TransitionSeal::from_str(seal)?, amount))
+}
+
+/// Contract subcommands
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+#[display(lowercase)]
+pub enum ContractSubCommand {
+    /// Shows contract details together with its genesis seals
+    Show {
+        /// Contract identifier
+        contract_id: ContractId,
+    },
+}
+
+/// Stock maintenance subcommands
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+#[display(lowercase)]
+pub enum StockSubCommand {
+    /// Merges another stock file into the local stash, keeping local data on conflicts
+    #[display("merge")]
+    Merge {
+        /// Stock file to merge from
+        file: PathBuf,
+
+        /// Merge contract histories with unmined or unknown witness transactions
+        #[clap(short, long)]
+        force: bool,
+    },
+    /// Re-validates all contracts and checks the consistency of the inventory indexes
+    Check,
+    /// Rebuilds the inventory indexes and contract state from the stash data
+    Reindex {
+        /// Drop bundles which have no anchor and thus cannot be indexed
+        #[clap(short, long)]
+        force: bool,
+
+        /// Do not resolve the witness transactions, indexing all of them as
+        /// unmined until the contracts are imported again
+        #[clap(long)]
+        offline: bool,
+    },
+}
+
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
 #[display(lowercase)]
 #[allow(clippy::large_enum_variant)]
@@ -59,10 +156,37 @@ pub enum RgbSubCommand {
     /// Prints out list of known RGB interfaces
     Interfaces,
     /// Prints out list of known RGB contracts
-    Contracts,
+    Contracts {
+        /// Print the schema, interfaces and RGB20 details of each contract
+        #[clap(long, alias = "json")]
+        details: bool,
+    },
 
-    // Set the rgb20 schemata and implementation
-    Set,
+    /// Contract details
+    #[display("contract")]
+    Contract {
+        #[clap(subcommand)]
+        subcommand: ContractSubCommand,
+    },
+
+    /// Stock maintenance
+    #[display("stock")]
+    Stock {
+        #[clap(subcommand)]
+        subcommand: StockSubCommand,
+    },
+
+    /// Imports the built-in schemata, interfaces and implementations into the stash
+    ///
+    /// Covers non-inflatable assets (NIA) with RGB20, unique digital assets
+    /// (UDA) with RGB21 and collectible fungible assets (CFA) with RGB25.
+    #[display("init")]
+    #[clap(alias = "set")]
+    Init {
+        /// Directory to also export the imported bindles to
+        #[clap(short, long)]
+        out: Option<PathBuf>,
+    },
     // generate random blinding factor
     Blinding,
     /// Imports RGB data into the stash: contracts, schema, interfaces, etc
@@ -112,14 +236,93 @@ pub enum RgbSubCommand {
         address: Option<String>,
     },
 
+    /// Lists all known operations of a contract with the state they spend and create
+    #[display("history")]
+    History {
+        /// Contract identifier
+        contract_id: ContractId,
+
+        /// Wallet address whose outputs are attributed to us, in addition to the
+        /// seals generated by this stash
+        #[clap(long, conflicts_with = "descriptor")]
+        address: Option<String>,
+
+        /// Descriptor of the wallet whose outputs are attributed to us, in
+        /// addition to the seals generated by this stash
+        #[clap(long)]
+        descriptor: Option<String>,
+
+        /// Descriptor of the internal addresses of the wallet
+        #[clap(long = "change_descriptor", requires = "descriptor")]
+        change_descriptor: Option<String>,
+    },
+
     /// Issues new contract
     #[display("issue")]
     Issue {
         /// Schema name to use for the contract
-        schema: SchemaId, //String,
+        #[clap(required_unless_present = "template")]
+        schema: Option<SchemaId>, //String,
+
+        /// File containing contract genesis description in YAML, JSON or TOML
+        /// format
+        #[clap(required_unless_present = "template")]
+        contract: Option<PathBuf>,
+
+        /// Prints a skeleton of the contract genesis description for the given
+        /// schema instead of issuing a contract
+        #[clap(long, requires = "iface", conflicts_with_all = ["schema", "contract", "dry_run"])]
+        template: Option<SchemaId>,
+
+        /// Interface name or id the template is generated for
+        #[clap(long, requires = "template")]
+        iface: Option<String>,
+
+        /// Format of the generated template
+        #[clap(long, value_enum, default_value = "yaml", requires = "template")]
+        format: IssueFormat,
+
+        /// Checks the contract genesis description, reporting all the errors,
+        /// without issuing the contract
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Issues a fungible asset with the RGB20 interface (NIA schema)
+    #[display("issue-rgb20")]
+    IssueRgb20 {
+        /// Asset ticker
+        #[clap(long)]
+        ticker: String,
+
+        /// Number of decimal digits of the asset amounts
+        #[clap(long, default_value = "8")]
+        precision: u8,
 
-        /// File containing contract genesis description in YAML format
-        contract: PathBuf,
+        #[clap(flatten)]
+        opts: IssueOpts,
+    },
+
+    /// Issues a collectible fungible asset with the RGB25 interface (CFA schema)
+    #[display("issue-rgb25")]
+    IssueRgb25 {
+        /// Number of decimal digits of the asset amounts
+        #[clap(long, default_value = "0")]
+        precision: u8,
+
+        #[clap(flatten)]
+        opts: IssueOpts,
+    },
+
+    /// Issues a unique digital asset with the RGB21 interface (UDA schema)
+    #[display("issue-rgb21")]
+    IssueRgb21 {
+        /// Asset ticker
+        #[clap(long)]
+        ticker: String,
+
+        #[clap(flatten)]
+        opts: IssueOpts,
     },
 
     /// Create new invoice
@@ -134,6 +337,58 @@ pub enum RgbSubCommand {
         /// Value to transfer
         value: u64,
         seal: String,
+
+        /// RGB proxy the payer posts the consignment to, like
+        /// rpcs://proxy.example.com/json-rpc
+        #[clap(long)]
+        proxy: Option<String>,
+    },
+
+    /// Posts a transfer consignment paying an invoice to the RGB proxy
+    ///
+    /// The consignment is kept by the proxy under the recipient id of the
+    /// invoice until the payee fetches it and answers with an ACK or a NACK.
+    #[display("send")]
+    Send {
+        /// Invoice being paid
+        invoice: RgbInvoice,
+
+        /// File with the transfer consignment
+        file: PathBuf,
+
+        /// RGB proxy to use instead of the transport of the invoice
+        #[clap(long)]
+        proxy: Option<String>,
+
+        /// Waits until the payee posts an ACK or a NACK
+        #[clap(long)]
+        wait: bool,
+
+        /// Seconds to wait for the ACK or the NACK before failing
+        #[clap(long, default_value = "600", requires = "wait")]
+        timeout: u64,
+    },
+
+    /// Fetches the consignment paying an invoice from the RGB proxy
+    ///
+    /// The consignment is validated and accepted into the stash, then an ACK
+    /// is posted back to the payer, or a NACK if it is not valid.
+    #[display("receive")]
+    Receive {
+        /// Invoice issued by this wallet
+        invoice: RgbInvoice,
+
+        /// RGB proxy to use instead of the transport of the invoice
+        #[clap(long)]
+        proxy: Option<String>,
+
+        /// Queue the transfer until its witness is mined, see `watch`
+        #[clap(short, long)]
+        queue: bool,
+
+        /// Also saves the consignment to a file
+        #[clap(short, long)]
+        out: Option<PathBuf>,
     },
 
     /// Inspects any RGB data file
@@ -161,9 +416,34 @@ pub enum RgbSubCommand {
         #[arg(short, long)]
         force: bool,
 
+        /// Queue the consignment until its terminal witness is mined, see
+        /// `watch`
+        #[arg(short, long, conflicts_with = "force")]
+        queue: bool,
+
         /// File with the transfer consignment
         file: PathBuf,
     },
+
+    /// Accepts the queued transfers once their witnesses are mined
+    ///
+    /// Polls the witness transactions of the transfers queued with `accept
+    /// --queue`, accepts each transfer at the given depth and flags the ones
+    /// whose witness was double-spent or reorged out.
+    #[display("watch")]
+    Watch {
+        /// Number of confirmations required to accept a transfer
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: u32,
+
+        /// Seconds between the checks
+        #[clap(long, default_value = "60")]
+        interval: u64,
+
+        /// Checks the queue once and prints the status of the transfers
+        #[clap(long)]
+        once: bool,
+    },
     #[display("sethost")]
     SetHost {
         #[clap(long, default_value = "opret1st")]
@@ -173,118 +453,283 @@ pub enum RgbSubCommand {
         /// PSBT file.
         psbt_file: PathBuf,
     },
+
+    /// Commits to the RGB transitions of a PSBT in its opret or tapret host output
+    ///
+    /// Builds the multi-protocol commitment over the transition bundles stored in
+    /// the PSBT, writes it into the host output and saves the resulting anchor and
+    /// bundles to the stash. Must be done before the PSBT is signed.
+    #[display("commit")]
+    Commit {
+        /// PSBT file.
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
+    },
+
+    /// Adds a state transition spending contract state to a PSBT
+    ///
+    /// The outpoints the state is spent from must be inputs of the PSBT. The
+    /// transition is stored in the PSBT proprietary keys, ready for `commit`.
+    #[display("psbt-add-transition")]
+    PsbtAddTransition {
+        /// PSBT file.
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
+
+        /// Contract identifier
+        #[clap(long = "contract")]
+        contract_id: ContractId,
+
+        /// Interface used to build the transition
+        #[clap(long)]
+        iface: String,
+
+        /// Transition name, if not the default transition of the interface
+        #[clap(long)]
+        transition: Option<String>,
+
+        /// Assignment name, if not the default assignment of the transition
+        #[clap(long)]
+        assignment: Option<String>,
+
+        /// Outpoint holding the contract state spent by the transition
+        #[clap(long = "from", required = true)]
+        from: Vec<Outpoint>,
+
+        /// New state assignment, where the seal is a vout of this PSBT, a
+        /// TXID:VOUT outpoint or a blinded seal from an invoice
+        #[clap(long = "to", required = true, value_parser = parse_transition_output)]
+        to: Vec<(TransitionSeal, u64)>,
+
+        /// Method for the new single-use-seals.
+        #[clap(long, default_value = "opret1st")]
+        method: CloseMethod,
+    },
 }
 
+#[allow(clippy::result_large_err)]
 pub fn handle_rgb_subcommand(
     data_dir: PathBuf,
     chain: &bdk::bitcoin::Network,
     electrum: Option<String>,
+    esplora: Option<String>,
+    psbt_version: Option<PsbtVer>,
     subcommand: RgbSubCommand,
 ) -> Result<serde_json::Value, anyhow::Error> {
-    let electrum = electrum.as_deref().unwrap_or_else(|| match chain {
-        Network::Bitcoin => "blockstream.info:110",
-        Network::Testnet => "blockstream.info:143",
-        _ => {
-            eprint!("No electrum server for this network");
-            std::process::exit(1);
-        }
-    });
-    let mut resolver = Resolver::new(electrum)?;
-    let mut runtime = Runtime::load(data_dir.clone(), chain).map_err(|err| anyhow!("{}", err))?;
+    let electrum = rgb_electrum(electrum, chain)?;
+    let esplora = rgb_esplora(esplora, chain)?;
+    let mut service = RgbService::load(data_dir, *chain, &esplora)?;
+    run_rgb_subcommand(
+        &mut service,
+        chain,
+        &electrum,
+        &esplora,
+        psbt_version,
+        subcommand,
+    )
+}
+
+/// Electrum server used for the network, unless one is given explicitly.
+pub fn rgb_electrum(electrum: Option<String>, chain: &Network) -> Result<String, RuntimeError> {
+    match (electrum, chain) {
+        (Some(electrum), _) => Ok(electrum),
+        (None, Network::Bitcoin) => Ok("blockstream.info:110".to_owned()),
+        (None, Network::Testnet) => Ok("blockstream.info:143".to_owned()),
+        (None, _) => Err(RuntimeError::usage(format!(
+            "no default electrum server for {chain}, please provide --electrum"
+        ))),
+    }
+}
+
+/// Esplora server resolving the witness transactions for the network, unless
+/// one is given explicitly.
+pub fn rgb_esplora(esplora: Option<String>, chain: &Network) -> Result<String, RuntimeError> {
+    match (esplora, chain) {
+        (Some(esplora), _) => Ok(esplora),
+        (None, Network::Bitcoin) => Ok("https://blockstream.info/api".to_owned()),
+        (None, Network::Testnet) => Ok("https://blockstream.info/testnet/api".to_owned()),
+        (None, _) => Err(RuntimeError::usage(format!(
+            "no default esplora server for {chain}, please provide --esplora"
+        ))),
+    }
+}
+
+/// Runs an RGB command against a stash which is already loaded.
+pub fn run_rgb_subcommand(
+    service: &mut RgbService,
+    chain: &bdk::bitcoin::Network,
+    electrum: &str,
+    esplora: &str,
+    psbt_version: Option<PsbtVer>,
+    subcommand: RgbSubCommand,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let RgbService {
+        runtime, resolver, ..
+    } = &mut *service;
 
     match subcommand {
-        RgbSubCommand::Schemata => {
-            for id in runtime.schema_ids()? {
-                print!("{id} ");
-                for iimpl in runtime.schema(id)?.iimpls.values() {
-                    let iface = runtime.iface_by_id(iimpl.iface_id)?;
-                    print!("{} ", iface.name);
-                }
-                println!();
+        RgbSubCommand::Schemata => Ok(serde_json::to_value(service.schemata()?)?),
+        RgbSubCommand::Interfaces => Ok(serde_json::to_value(service.interfaces()?)?),
+        RgbSubCommand::Contracts { details } => {
+            let contracts = service.contracts()?;
+            if !details {
+                let ids = contracts
+                    .iter()
+                    .map(ContractId::to_string)
+                    .collect::<Vec<_>>();
+                return Ok(json!(ids));
             }
-            Ok(JsonNull)
-        }
-        RgbSubCommand::Interfaces => {
-            for (id, name) in runtime.ifaces()? {
-                println!("{} {id}", name);
+            let mut summaries = vec![];
+            for id in contracts {
+                summaries.push(service.contract(id)?);
             }
-            Ok(JsonNull)
+            Ok(serde_json::to_value(summaries)?)
         }
-        RgbSubCommand::Contracts => {
-            for id in runtime.contract_ids()? {
-                println!("{id}");
-            }
-            Ok(JsonNull)
+        RgbSubCommand::Stock {
+            subcommand: StockSubCommand::Merge { file, force },
+        } => {
+            let other = Stock::load(&file).map_err(RuntimeError::from)?;
+            let report = merge_stock(runtime, &other, resolver, force)?;
+            runtime.store()?;
+            eprintln!("Stock {} merged into the stash", file.display());
+            Ok(report)
         }
-        RgbSubCommand::Blinding => {
-            let blinding_factor = BlindingFactor::random().to_hex();
-            eprintln!("blinding_factor {blinding_factor}");
-            Ok(JsonNull)
+        RgbSubCommand::Stock {
+            subcommand: StockSubCommand::Check,
+        } => check_stock(runtime, resolver),
+        RgbSubCommand::Stock {
+            subcommand: StockSubCommand::Reindex { force, offline },
+        } => {
+            let (stock, report) = if offline {
+                reindex_stock(runtime, &mut OfflineResolver, force)?
+            } else {
+                reindex_stock(runtime, resolver, force)?
+            };
+            let backup_path = runtime.stock_path().with_extension("dat.bak");
+            fs::copy(runtime.stock_path(), &backup_path)?;
+            runtime.replace_stock(stock);
+            runtime.store()?;
+            eprintln!(
+                "Stock reindexed, the previous one is kept in {}",
+                backup_path.display()
+            );
+            Ok(report)
         }
-        RgbSubCommand::Set => {
-            let schema_bindle = nia_schema().bindle();
-            eprintln!("schema_bindle {schema_bindle}");
+        RgbSubCommand::Contract {
+            subcommand: ContractSubCommand::Show { contract_id },
+        } => {
+            let mut contract = serde_json::to_value(service.contract(contract_id)?)?;
+            let genesis = service.runtime().genesis(contract_id)?;
+            let schema_ifaces = service.runtime().contract_schema(contract_id)?;
 
-            schema_bindle.save("your_custom_path/demo/rgb-schemata/NonInflatableAssets.rgb")?;
-            let iimpl_bindle = nia_rgb20().bindle();
-            eprintln!("iimpl_bindle {iimpl_bindle}");
+            let mut seals = vec![];
+            for (ty, assigns) in genesis.assignments.iter() {
+                let name = schema_ifaces
+                    .iimpls
+                    .values()
+                    .flat_map(|iimpl| iimpl.assignments.iter())
+                    .find(|info| info.id == *ty)
+                    .map(|info| info.name.to_string());
+                for index in 0..assigns.len_u16() {
+                    let amount = assigns
+                        .as_fungible()
+                        .get(index as usize)
+                        .and_then(|assign| assign.as_revealed_state())
+                        .map(|state| state.value.as_u64());
+                    seals.push(json!({
+                        "assignment_type": ty,
+                        "assignment": name,
+                        "seal": assigns
+                            .revealed_seal_at(index)
+                            .ok()
+                            .flatten()
+                            .map(|seal| seal.to_string()),
+                        "amount": amount,
+                    }));
+                }
+            }
+            contract["testnet"] = json!(genesis.testnet);
+            contract["genesis_seals"] = json!(seals);
+            Ok(contract)
+        }
+        RgbSubCommand::Blinding => Ok(serde_json::to_value(service.blinding())?),
+        RgbSubCommand::Init { out } => {
+            if let Some(out) = &out {
+                fs::create_dir_all(out)?;
+            }
 
-            iimpl_bindle
-                .save("your_custom_path/demo/rgb-schemata/NonInflatableAssets-RGB20.rgb")?;
-            Ok(JsonNull)
+            let mut interfaces = vec![];
+            for iface in [rgb20(), rgb21(), rgb25()] {
+                let bindle = iface.bindle();
+                if let Some(out) = &out {
+                    bindle.save(out.join(format!("{}.rgb", bindle.name)))?;
+                }
+                interfaces.push(json!({
+                    "id": bindle.id().to_string(),
+                    "name": bindle.name.to_string(),
+                }));
+                runtime.import_iface(bindle).map_err(RuntimeError::from)?;
+            }
+
+            let mut schemata = vec![];
+            for (name, schema, iimpl) in [
+                ("NonInflatableAssets", nia_schema(), nia_rgb20()),
+                ("UniqueDigitalAsset", uda_schema(), uda_rgb21()),
+                ("CollectibleFungibleAssets", cfa_schema(), cfa_rgb25()),
+            ] {
+                let iface_name = runtime.iface_by_id(iimpl.iface_id)?.name.clone();
+                let schema_bindle = schema.bindle();
+                let iimpl_bindle = iimpl.bindle();
+                if let Some(out) = &out {
+                    schema_bindle.save(out.join(format!("{name}.rgb")))?;
+                    iimpl_bindle.save(out.join(format!("{name}-{iface_name}.rgb")))?;
+                }
+                schemata.push(json!({
+                    "name": name,
+                    "id": schema_bindle.id().to_string(),
+                    "interface": iface_name.to_string(),
+                    "implementation": iimpl_bindle.id().to_string(),
+                }));
+                runtime
+                    .import_schema(schema_bindle)
+                    .map_err(RuntimeError::from)?;
+                runtime
+                    .import_iface_impl(iimpl_bindle)
+                    .map_err(RuntimeError::from)?;
+            }
+            runtime.store()?;
+
+            eprintln!("Built-in schemata imported to the stash");
+            if let Some(out) = &out {
+                eprintln!("Bindles exported to {}", out.display());
+            }
+            Ok(json!({
+                "interfaces": interfaces,
+                "schemata": schemata,
+            }))
         }
         RgbSubCommand::Import { armored, file } => {
             if armored {
-                todo!()
-            } else {
-                let bindle = UniversalBindle::load_file(file)?;
-
-                match bindle {
-                    UniversalBindle::Iface(iface) => {
-                        let id = iface.id();
-                        let name = iface.name.clone();
-                        runtime
-                            .import_iface(iface)
-                            .map_err(|err| anyhow!("{}", err))?;
-                        eprintln!("Interface {id} with name {name} imported to the stash");
-                    }
-                    UniversalBindle::Schema(schema) => {
-                        let id = schema.id();
-                        runtime
-                            .import_schema(schema)
-                            .map_err(|err| anyhow!("{}", err))?;
-                        eprintln!("Schema {id} imported to the stash");
-                    }
-                    UniversalBindle::Impl(iimpl) => {
-                        let iface_id = iimpl.iface_id;
-                        let schema_id = iimpl.schema_id;
-                        let id = iimpl.id();
-                        runtime
-                            .import_iface_impl(iimpl)
-                            .map_err(|err| anyhow!("{}", err))?;
-                        eprintln!(
-                            "Implementation {id} of interface {iface_id} for schema \
-                             {schema_id} imported to the stash"
-                        );
-                    }
-                    UniversalBindle::Contract(bindle) => {
-                        let id = bindle.id();
-                        let contract =
-                            bindle
-                                .unbindle()
-                                .validate(&mut resolver, true)
-                                .map_err(|c| {
-                                    anyhow!("{}", c.validation_status().expect("just validated"))
-                                })?;
-                        runtime
-                            .import_contract(contract, &mut resolver)
-                            .map_err(|err| anyhow!("{}", err))?;
-                        eprintln!("Contract {id} imported to the stash");
-                    }
-                    UniversalBindle::Transfer(_) => todo!(),
-                };
+                return Err(RuntimeError::unsupported("import of ASCII-armored files").into());
+            }
+            let bindle = UniversalBindle::load_file(&file)?;
+            let imported = service.import(bindle)?;
+            match &imported {
+                Imported::Interface { id, name } => {
+                    eprintln!("Interface {id} with name {name} imported to the stash")
+                }
+                Imported::Schema { id } => eprintln!("Schema {id} imported to the stash"),
+                Imported::Implementation {
+                    id,
+                    iface_id,
+                    schema_id,
+                } => eprintln!(
+                    "Implementation {id} of interface {iface_id} for schema {schema_id} \
+                     imported to the stash"
+                ),
+                Imported::Contract { id } => eprintln!("Contract {id} imported to the stash"),
             }
-            Ok(JsonNull)
+            Ok(serde_json::to_value(imported)?)
         }
 
         RgbSubCommand::Export {
@@ -292,9 +737,7 @@ pub fn handle_rgb_subcommand(
             contract,
             file,
         } => {
-            let bindle = runtime
-                .export_contract(contract)
-                .map_err(|err| anyhow!("{}", err))?;
+            let bindle = service.export(contract)?;
             if let Some(file) = file {
                 if armored {
                     const PLATE_TITLE: &str = "RGB CONTRACT";
@@ -314,7 +757,9 @@ pub fn handle_rgb_subcommand(
                     writeln!(f)?;
 
                     // TODO: Replace with streamed writer
-                    let bin = data.to_strict_serialized::<U24>().expect("in-memory");
+                    let bin = data
+                        .to_strict_serialized::<U24>()
+                        .map_err(|err| RuntimeError::Internal(err.to_string()))?;
                     let hex_str = base64::encode(&bin);
                     let mut str_ref = hex_str.as_str();
                     while str_ref.len() >= 64 {
@@ -329,10 +774,16 @@ pub fn handle_rgb_subcommand(
                     bindle.save(&file)?;
                 }
                 eprintln!("Contract {contract} exported to '{}'", file.display());
+                Ok(json!({
+                    "contract_id": contract.to_string(),
+                    "file": file.display().to_string(),
+                }))
             } else {
-                println!("{bindle}");
+                Ok(json!({
+                    "contract_id": contract.to_string(),
+                    "bindle": bindle.to_string(),
+                }))
             }
-            Ok(JsonNull)
         }
 
         RgbSubCommand::State {
@@ -341,295 +792,201 @@ pub fn handle_rgb_subcommand(
             iface,
             address,
         } => {
-            let iface = runtime.iface_by_name(&tn!(iface.to_owned()))?.clone();
-            let contract = runtime
-                .contract_iface_id(contract_id, iface.iface_id())
-                .map_err(|err| anyhow!("{}", err))?;
-
-            println!("Global:");
-            for global in &contract.iface.global_state {
-                if let Ok(values) = contract.global(global.name.clone()) {
-                    for val in values {
-                        println!("  {} := {}", global.name, val);
-                    }
-                }
-            }
-
-            let utxo = address.map(|address| {
-                let client = bdk::electrum_client::Client::new(&electrum).unwrap();
-                client
-                    .script_list_unspent(
-                        &bdk::bitcoin::Address::from_str(&address)
-                            .unwrap()
-                            .require_network(*chain)
-                            .unwrap()
-                            .script_pubkey(),
+            let utxos = match address {
+                Some(address) => {
+                    let script = bdk::bitcoin::Address::from_str(&address)
+                        .map_err(|err| RuntimeError::usage(format!("invalid address: {err}")))?
+                        .require_network(*chain)
+                        .map_err(|err| RuntimeError::usage(format!("invalid address: {err}")))?
+                        .script_pubkey();
+                    let client = bdk::electrum_client::Client::new(electrum)
+                        .map_err(RuntimeError::network)?;
+                    let unspent = client
+                        .script_list_unspent(&script)
+                        .map_err(RuntimeError::network)?;
+                    Some(
+                        unspent
+                            .into_iter()
+                            .map(|x| bdk::bitcoin::OutPoint::new(x.tx_hash, x.tx_pos as u32))
+                            .collect(),
                     )
-                    .unwrap()
-                    .into_iter()
-                    .map(|x| format!("{}:{}", x.tx_hash, x.tx_pos))
-                    .collect::<HashSet<_>>()
-            });
-
-            println!("\nOwned:");
-            for owned in &contract.iface.assignments {
-                println!("  {}:", owned.name);
-                if let Some(utxo) = utxo {
-                    if let Ok(allocations) = contract.fungible(owned.name.clone(), &runtime) {
-                        allocations
-                            .iter()
-                            .filter(|x| {
-                                let seal = x.owner.as_reduced_unsafe();
-                                let outpoint = seal.txid.map_to_outpoint(seal.vout).unwrap();
-                                utxo.contains(&outpoint.to_string())
-                            })
-                            .for_each(|x| {
-                                println!(
-                                    "    amount={}, utxo={}, witness={} # owned by the wallet",
-                                    x.value, x.owner, x.witness
-                                );
-                            });
-                    }
-                    return Ok(JsonNull);
-                }
-                if let Ok(allocations) = contract.fungible(owned.name.clone(), &runtime) {
-                    for allocation in allocations {
-                        println!(
-                            "    amount={}, utxo={}, witness={} # owned by the wallet",
-                            allocation.value, allocation.owner, allocation.witness
-                        );
-                    }
                 }
-                if all {
-                    if let Ok(allocations) =
-                        contract.fungible(owned.name.clone(), &FilterExclude(&runtime))
-                    {
-                        for allocation in allocations {
-                            println!(
-                                "    amount={}, utxo={}, witness={} # owner unknown",
-                                allocation.value, allocation.owner, allocation.witness
-                            );
-                        }
-                    }
+                None => None,
+            };
+            let state = service.state(StateRequest {
+                contract_id,
+                iface,
+                all,
+                utxos,
+            })?;
+            Ok(serde_json::to_value(state)?)
+        }
+        RgbSubCommand::History {
+            contract_id,
+            address,
+            descriptor,
+            change_descriptor,
+        } => {
+            let mut ownership =
+                Ownership::with_seal_secrets(runtime.seal_secrets().map_err(RuntimeError::from)?);
+            if let Some(address) = address {
+                let script = bdk::bitcoin::Address::from_str(&address)
+                    .map_err(|err| RuntimeError::usage(format!("invalid address: {err}")))?
+                    .require_network(*chain)
+                    .map_err(|err| RuntimeError::usage(format!("invalid address: {err}")))?
+                    .script_pubkey();
+                let client = bdk::electrum_client::Client::new(electrum)?;
+                for item in client.script_get_history(&script)? {
+                    let tx = client.transaction_get(&item.tx_hash)?;
+                    let txid = bp::Txid::from_str(&item.tx_hash.to_string())?;
+                    ownership.add_outpoints(
+                        tx.output
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, txout)| txout.script_pubkey == script)
+                            .map(|(vout, _)| Outpoint::new(txid, vout as u32)),
+                    );
                 }
-                // TODO: Print out other types of state
             }
-            Ok(JsonNull)
+            if let Some(descriptor) = descriptor {
+                let wallet = bdk::Wallet::new(
+                    descriptor.as_str(),
+                    change_descriptor.as_deref(),
+                    *chain,
+                    MemoryDatabase::default(),
+                )?;
+                let client = bdk::electrum_client::Client::new(electrum)?;
+                wallet.sync(&ElectrumBlockchain::from(client), SyncOptions::default())?;
+                ownership.add_wallet(&wallet)?;
+            }
+            contract_history(runtime, resolver, contract_id, &ownership)
         }
-        RgbSubCommand::Issue { schema, contract } => {
-            let file = fs::File::open(contract)?;
-
-            let code = serde_yaml::from_reader::<_, serde_yaml::Value>(file)?;
-
-            let code = code
-                .as_mapping()
-                .expect("invalid YAML root-level structure");
-
-            let iface_name = code
-                .get("interface")
-                .expect("contract must specify interface under which it is constructed")
-                .as_str()
-                .expect("interface name must be a string");
-            let SchemaIfaces {
-                ref schema,
-                ref iimpls,
-            } = runtime.schema(schema)?;
-            let iface_name = tn!(iface_name.to_owned());
-            let iface = runtime
-                .iface_by_name(&iface_name)
-                .or_else(|_| {
-                    let id = IfaceId::from_str(iface_name.as_str())?;
-                    runtime.iface_by_id(id).map_err(RuntimeError::from)
-                })
-                .expect("get iface failed")
-                .clone();
-            let iface_id = iface.iface_id();
-            let iface_impl = iimpls
-                .get(&iface_id)
-                .ok_or_else(|| {
-                    RuntimeError::Custom(format!(
-                        "no known interface implementation for {iface_name}"
-                    ))
-                })
-                .expect("get iface impl failed");
-            let types = &schema.type_system;
-
-            let mut builder =
-                ContractBuilder::testnet(iface.clone(), schema.clone(), iface_impl.clone())?;
-
-            if let Some(globals) = code.get("globals") {
-                for (name, val) in globals
-                    .as_mapping()
-                    .expect("invalid YAML: globals must be an mapping")
-                {
-                    let name = name
-                        .as_str()
-                        .expect("invalid YAML: global name must be a string");
-                    let name = iface
-                        .genesis
-                        .global
-                        .iter()
-                        .find(|(n, _)| n.as_str() == name)
-                        .and_then(|(_, spec)| spec.name.as_ref())
-                        .map(FieldName::as_str)
-                        .unwrap_or(name);
-                    let state_type = iface_impl
-                        .global_state
-                        .iter()
-                        .find(|info| info.name.as_str() == name)
-                        .unwrap_or_else(|| panic!("unknown type name '{name}'"))
-                        .id;
-                    let sem_id = schema
-                        .global_types
-                        .get(&state_type)
-                        .expect("invalid schema implementation")
-                        .sem_id;
-                    let val = StrictVal::from(val.clone());
-                    let typed_val = types
-                        .typify(val, sem_id)
-                        .expect("global type doesn't match type definition");
-
-                    let serialized = types
-                        .strict_serialize_type::<U16>(&typed_val)
-                        .expect("internal error");
-                    // Workaround for borrow checker:
-                    let field_name =
-                        FieldName::try_from(name.to_owned()).expect("invalid type name");
-                    builder = builder
-                        .add_global_state(field_name, serialized)
-                        .expect("invalid global state data");
-                }
+        RgbSubCommand::Issue {
+            schema,
+            contract,
+            template,
+            iface,
+            format,
+            dry_run,
+        } => {
+            if let Some(schema_id) = template {
+                let iface = iface.ok_or_else(|| RuntimeError::usage("--iface is required"))?;
+                let template = issue_template(runtime, schema_id, &iface, format)?;
+                return Ok(json!({ "template": template }));
             }
-
-            if let Some(assignments) = code.get("assignments") {
-                for (name, val) in assignments
-                    .as_mapping()
-                    .expect("invalid YAML: assignments must be an mapping")
-                {
-                    let name = name
-                        .as_str()
-                        .expect("invalid YAML: assignments name must be a string");
-                    let name = iface
-                        .genesis
-                        .assignments
-                        .iter()
-                        .find(|(n, _)| n.as_str() == name)
-                        .and_then(|(_, spec)| spec.name.as_ref())
-                        .map(FieldName::as_str)
-                        .unwrap_or(name);
-                    let state_type = iface_impl
-                        .assignments
-                        .iter()
-                        .find(|info| info.name.as_str() == name)
-                        .expect("unknown type name")
-                        .id;
-                    let state_schema = schema
-                        .owned_types
-                        .get(&state_type)
-                        .expect("invalid schema implementation");
-
-                    let assign = val.as_mapping().expect("an assignment must be a mapping");
-                    let seal = assign
-                        .get("seal")
-                        .expect("assignment doesn't provide seal information")
-                        .as_str()
-                        .expect("seal must be a string");
-
-                    let seal = OutputSeal::from_str(seal).expect("invalid seal definition");
-                    let seal = GenesisSeal::new_random(seal.method, seal.txid, seal.vout);
-
-                    // Workaround for borrow checker:
-                    let field_name =
-                        FieldName::try_from(name.to_owned()).expect("invalid type name");
-                    match state_schema.state_type() {
-                        StateType::Void => todo!(),
-                        StateType::Fungible => {
-                            let amount = assign
-                                .get("amount")
-                                .expect("owned state must be a fungible amount")
-                                .as_u64()
-                                .expect("fungible state must be an integer");
-                            let seal = BuilderSeal::Revealed(XChain::Bitcoin(seal));
-                            builder = builder
-                                .add_fungible_state(field_name, seal, amount)
-                                .expect("invalid global state data");
-                        }
-                        StateType::Structured => todo!(),
-                        StateType::Attachment => todo!(),
-                    }
-                }
+            let (Some(schema_id), Some(file)) = (schema, contract) else {
+                return Err(RuntimeError::usage("schema and contract file are required").into());
+            };
+            let contract = issue_from_file(runtime, schema_id, &file, *chain == Network::Bitcoin)?;
+            if dry_run {
+                eprintln!("No errors found in {}", file.display());
+                return Ok(json!({
+                    "file": file.display().to_string(),
+                    "valid": true,
+                }));
             }
-
-            let contract = builder.issue_contract().expect("failure issuing contract");
-            let id = contract.contract_id();
-            let validated_contract = contract
-                .validate(&mut resolver, true)
-                .map_err(|consignment| {
-                    RuntimeError::IncompleteContract(
-                        consignment
-                            .into_validation_status()
-                            .expect("just validated"),
-                    )
-                })
-                .unwrap();
-            runtime
-                .import_contract(validated_contract, &mut resolver)
-                .expect("failure importing issued contract");
-            eprintln!(
-                "A new contract {id} is issued and added to the stash.\nUse `export` command \
-                     to export the contract."
-            );
-            Ok(JsonNull)
+            import_issued(service, contract, None)
+        }
+        RgbSubCommand::IssueRgb20 {
+            ticker,
+            precision,
+            opts,
+        } => {
+            let contract = issue_rgb20(&ticker, precision, &opts, *chain == Network::Bitcoin)?;
+            import_issued(service, contract, opts.out)
+        }
+        RgbSubCommand::IssueRgb25 { precision, opts } => {
+            let contract = issue_rgb25(precision, &opts, *chain == Network::Bitcoin)?;
+            import_issued(service, contract, opts.out)
+        }
+        RgbSubCommand::IssueRgb21 { ticker, opts } => {
+            let contract = issue_rgb21(&ticker, &opts, *chain == Network::Bitcoin)?;
+            import_issued(service, contract, opts.out)
         }
         RgbSubCommand::Inspect { file, format } => {
-            let bindle = UniversalBindle::load_file(file)?;
+            let bindle = UniversalBindle::load_file(&file)?;
             // TODO: For now, serde implementation doesn't work for consignments due to
             //       some of the keys which can't be serialized to strings. Once this fixed,
             //       allow this inspect formats option
-            let s = match format {
+            let unsupported = |err: &dyn std::fmt::Display| {
+                RuntimeError::unsupported(format!("unable to present the file as {format}: {err}"))
+            };
+            let content = match format {
                 InspectFormat::Yaml => {
-                    serde_yaml::to_string(&bindle).expect("unable to present as YAML")
+                    json!(serde_yaml::to_string(&bindle).map_err(|err| unsupported(&err))?)
+                }
+                InspectFormat::Toml => {
+                    json!(toml::to_string(&bindle).map_err(|err| unsupported(&err))?)
                 }
-                InspectFormat::Toml => toml::to_string(&bindle).expect("unable to present as TOML"),
                 InspectFormat::Json => {
-                    serde_json::to_string(&bindle).expect("unable to present as JSON")
+                    serde_json::to_value(&bindle).map_err(|err| unsupported(&err))?
+                }
+                InspectFormat::Debug => json!(format!("{bindle:#?}")),
+                InspectFormat::Contractum => {
+                    return Err(RuntimeError::unsupported("contractum representation").into())
                 }
-                InspectFormat::Debug => format!("{bindle:#?}"),
-                InspectFormat::Contractum => todo!("contractum representation"),
             };
-            println!("{s}");
-            Ok(JsonNull)
+            Ok(json!({
+                "file": file.display().to_string(),
+                "content": content,
+            }))
         }
 
         RgbSubCommand::Validate { file } => {
-            let bindle = Bindle::<Transfer>::load_file(file)?;
-            let consignment = bindle.unbindle();
-            resolver.add_terminals(&consignment);
-            let status = match consignment.validate(&mut resolver, true) {
-                Ok(consignment) => consignment.into_validation_status(),
-                Err(consignment) => consignment.into_validation_status(),
-            }
-            .expect("just validated");
-            if status.validity() == Validity::Valid {
-                eprintln!("The provided consignment is valid")
-            } else {
-                eprintln!("{status}");
+            let bindle = Bindle::<Transfer>::load_file(&file)?;
+            let report = service.validate(bindle.unbindle())?;
+            match &report.status {
+                Some(status) if report.validity != ConsignmentValidity::Valid => {
+                    eprintln!("{status}")
+                }
+                _ => eprintln!("The provided consignment is valid"),
             }
-            Ok(JsonNull)
+            let mut report = serde_json::to_value(report)?;
+            report["file"] = json!(file.display().to_string());
+            Ok(report)
         }
-        RgbSubCommand::Accept { force, file } => {
+        RgbSubCommand::Accept {
+            queue: true, file, ..
+        } => {
             let bindle = Bindle::<Transfer>::load_file(file)?;
-            let consignment = bindle.unbindle();
-            resolver.add_terminals(&consignment);
-            let transfer = consignment
-                .validate(&mut resolver, true)
-                .unwrap_or_else(|c| c);
-            eprintln!("{}", transfer.validation_status().expect("just validated"));
-            runtime
-                .accept_transfer(transfer, &mut resolver, force)
-                .unwrap();
+            let pending = enqueue(service, bindle)?;
+            eprintln!(
+                "Transfer queued until its witness {} is mined",
+                pending
+                    .witnesses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Ok(serde_json::to_value(pending)?)
+        }
+        RgbSubCommand::Accept { force, file, .. } => {
+            let bindle = Bindle::<Transfer>::load_file(file)?;
+            let report = service.accept(bindle.unbindle(), force)?;
+            if let Some(status) = &report.status {
+                eprintln!("{status}");
+            }
             eprintln!("Transfer accepted into the stash");
-            Ok(JsonNull)
+            Ok(serde_json::to_value(report)?)
+        }
+        RgbSubCommand::Watch {
+            depth,
+            interval,
+            once,
+        } => {
+            if once {
+                let report = watch_once(service, electrum, esplora, depth)?;
+                return Ok(serde_json::to_value(report)?);
+            }
+            eprintln!("Watching the queued transfers every {interval}s, press Ctrl-C to stop");
+            loop {
+                // Keep watching when the server is not reachable for a while
+                if let Err(err) = watch_once(service, electrum, esplora, depth) {
+                    eprintln!("ERROR: {}", RuntimeError::classify(err));
+                }
+                std::thread::sleep(std::time::Duration::from_secs(interval));
+            }
         }
         RgbSubCommand::SetHost { psbt_file, method } => {
             let psbt_data = fs::read(&psbt_file)?;
@@ -643,11 +1000,12 @@ pub fn handle_rgb_subcommand(
                         .zip(&mut psbt.outputs_mut())
                         .find(|(o, outp)| o.script_pubkey.is_op_return() && !outp.is_opret_host())
                         .and_then(|(_, outp)| {
-                            psbt_modified = true;
                             outp.set_rgb_velocity_hint(
                                 rgbstd::interface::VelocityHint::Unspecified,
                             );
-                            outp.set_opret_host().ok()
+                            let res = outp.set_opret_host().ok();
+                            psbt_modified = res.is_some();
+                            res
                         });
                 }
                 CloseMethod::TapretFirst => {
@@ -657,51 +1015,288 @@ pub fn handle_rgb_subcommand(
                         .zip(&mut psbt.outputs_mut())
                         .find(|(o, outp)| o.script_pubkey.is_p2tr() && !outp.is_tapret_host())
                         .and_then(|(_, outp)| {
-                            psbt_modified = true;
-                            outp.set_tapret_host().ok()
+                            let res = outp.set_tapret_host().ok();
+                            psbt_modified = res.is_some();
+                            res
                         });
                 }
             };
-            fs::write(&psbt_file, psbt.serialize(PsbtVer::V0))?;
+            fs::write(
+                &psbt_file,
+                psbt.serialize(psbt_version.unwrap_or(psbt.version)),
+            )?;
 
             if psbt_modified {
                 eprintln!(
                     "PSBT file '{}' is updated with {method} host now set.",
                     psbt_file.display()
                 );
+            } else {
+                eprintln!(
+                    "PSBT file '{}' has no output which can host {method} commitment.",
+                    psbt_file.display()
+                );
             }
-            Ok(JsonNull)
+            Ok(json!({
+                "psbt_file": psbt_file.display().to_string(),
+                "method": method.to_string(),
+                "modified": psbt_modified,
+            }))
+        }
+        RgbSubCommand::Commit { psbt_file } => {
+            let psbt_data = fs::read(&psbt_file)?;
+            let mut psbt = Psbt::deserialize(&psbt_data)?;
+            // The commitment changes the host output, so the outputs must be final
+            psbt.complete_construction();
+            let fascia = psbt.rgb_commit()?;
+            let txid = psbt.txid();
+
+            let mut outputs = vec![];
+            for (vout, output) in psbt.outputs().enumerate() {
+                if let Ok(commitment) = output.opret_commitment() {
+                    outputs.push(json!({
+                        "vout": vout,
+                        "method": CloseMethod::OpretFirst.to_string(),
+                        "commitment": commitment.to_string(),
+                        "script_pubkey": output.script.to_hex(),
+                    }));
+                }
+                if let Ok(commitment) = output.tapret_commitment() {
+                    outputs.push(json!({
+                        "vout": vout,
+                        "method": CloseMethod::TapretFirst.to_string(),
+                        "commitment": commitment.to_string(),
+                        "script_pubkey": output.script.to_hex(),
+                    }));
+                }
+            }
+            let contracts = fascia
+                .bundles
+                .iter()
+                .map(|(contract_id, bundle)| {
+                    json!({
+                        "contract_id": contract_id.to_string(),
+                        "bundle_id": bundle.bundle_id().to_string(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            runtime.consume(fascia).map_err(RuntimeError::from)?;
+            runtime.store()?;
+
+            fs::write(
+                &psbt_file,
+                psbt.serialize(psbt_version.unwrap_or(psbt.version)),
+            )?;
+            eprintln!(
+                "PSBT file '{}' is updated with the RGB commitment, anchor for witness {txid} \
+                 is saved to the stash.",
+                psbt_file.display()
+            );
+            Ok(json!({
+                "txid": txid.to_string(),
+                "commitments": outputs,
+                "contracts": contracts,
+            }))
+        }
+        RgbSubCommand::PsbtAddTransition {
+            psbt_file,
+            contract_id,
+            iface,
+            transition,
+            assignment,
+            from,
+            to,
+            method,
+        } => {
+            let psbt_data = fs::read(&psbt_file)?;
+            let mut psbt = Psbt::deserialize(&psbt_data)?;
+
+            let iface = TypeName::try_from(iface).map_err(RuntimeError::usage)?;
+            let transition = transition
+                .map(TypeName::try_from)
+                .transpose()
+                .map_err(RuntimeError::usage)?;
+            let mut builder = runtime
+                .transition_builder(contract_id, iface, transition)
+                .map_err(RuntimeError::from)?;
+            let assignment = match assignment {
+                Some(name) => FieldName::try_from(name).map_err(RuntimeError::usage)?,
+                None => builder.default_assignment()?.clone(),
+            };
+            let assignment_type = builder.assignments_type(&assignment).ok_or_else(|| {
+                RuntimeError::usage(format!("transition has no assignment named '{assignment}'"))
+            })?;
+
+            let mut inputs = vec![];
+            let mut input_sum = 0u64;
+            for ((opout, seal), state) in runtime
+                .state_for_outpoints(contract_id, from.iter().copied().map(XChain::Bitcoin))
+                .map_err(RuntimeError::from)?
+            {
+                if opout.ty != assignment_type {
+                    return Err(RuntimeError::unsupported(format!(
+                        "outpoint {} also holds state of assignment type {}",
+                        seal, opout.ty
+                    ))
+                    .into());
+                }
+                if let TypedState::Amount(value, _, _) = state {
+                    input_sum += value;
+                }
+                builder = builder.add_input(opout, state)?;
+                inputs.push(seal);
+            }
+            for outpoint in &from {
+                if !inputs
+                    .iter()
+                    .any(|seal| XOutpoint::from(*seal) == XChain::Bitcoin(*outpoint))
+                {
+                    return Err(RuntimeError::usage(format!(
+                        "outpoint {outpoint} holds no state of contract {contract_id}"
+                    ))
+                    .into());
+                }
+            }
+
+            let output_sum = to.iter().map(|(_, amount)| amount).sum::<u64>();
+            if input_sum != output_sum {
+                return Err(RuntimeError::usage(format!(
+                    "transition spends {input_sum} but assigns {output_sum}, the amounts must \
+                     be equal"
+                ))
+                .into());
+            }
+
+            // Check the number of inputs and assignments against the schema
+            let schema = runtime
+                .contract_schema(contract_id)
+                .map_err(RuntimeError::from)?
+                .schema
+                .clone();
+            let transition_schema = schema
+                .transitions
+                .get(&builder.transition_type())
+                .ok_or_else(|| {
+                    RuntimeError::Custom(format!(
+                        "transition type {} is not defined by the schema",
+                        builder.transition_type()
+                    ))
+                })?;
+            if let Some(occurrences) = transition_schema.inputs.get(&assignment_type) {
+                occurrences.check(inputs.len() as u16).map_err(|err| {
+                    RuntimeError::usage(format!("invalid number of inputs: {err}"))
+                })?;
+            }
+            if let Some(occurrences) = transition_schema.assignments.get(&assignment_type) {
+                occurrences.check(to.len() as u16).map_err(|err| {
+                    RuntimeError::usage(format!("invalid number of assignments: {err}"))
+                })?;
+            }
+
+            let mut outputs = vec![];
+            for (seal, amount) in to {
+                let builder_seal = seal.to_builder_seal(method);
+                if let BuilderSeal::Revealed(graph_seal) = builder_seal {
+                    runtime
+                        .store_seal_secret(graph_seal)
+                        .map_err(RuntimeError::from)?;
+                }
+                builder = builder.add_fungible_state_raw(
+                    assignment_type,
+                    builder_seal,
+                    amount,
+                    BlindingFactor::random(),
+                )?;
+                outputs.push(json!({
+                    "seal": seal.to_string(),
+                    "amount": amount,
+                }));
+            }
+
+            let transition = builder.complete_transition(contract_id)?;
+            let opid = transition.id();
+            let transition_type = transition.transition_type;
+            psbt.rgb_embed(Batch {
+                main: TransitionInfo::new(transition, inputs.as_slice()).map_err(|err| {
+                    RuntimeError::usage(format!("invalid transition inputs: {err}"))
+                })?,
+                blanks: Default::default(),
+            })?;
+            runtime.store()?;
+
+            fs::write(
+                &psbt_file,
+                psbt.serialize(psbt_version.unwrap_or(psbt.version)),
+            )?;
+            eprintln!(
+                "PSBT file '{}' is updated with state transition {opid}.",
+                psbt_file.display()
+            );
+            Ok(json!({
+                "contract_id": contract_id.to_string(),
+                "opid": opid.to_string(),
+                "transition_type": transition_type.to_inner(),
+                "inputs": inputs.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                "outputs": outputs,
+            }))
         }
         RgbSubCommand::Invoice {
             contract_id,
             iface,
             value,
             seal,
+            proxy,
         } => {
-            let iface = TypeName::try_from(iface.to_owned()).expect("invalid interface name");
-            let network = bpstd::Network::Testnet3;
-            let mut output_vec = seal.split(":");
-            let txid_str = output_vec.next().unwrap();
-            let vout_str = output_vec.next().unwrap();
-
-            let output = Outpoint::new(
-                Array::from_str(txid_str).unwrap().into(),
-                Vout::from_str(vout_str).unwrap(),
-            );
-            let graph_seal = XChain::Bitcoin(GraphSeal::opret_first_rand_from(output));
-            runtime
-                .store_seal_secret(graph_seal)
-                .expect("store seal secret error");
-            let beneficiary =
-                Beneficiary::BlindedSeal(*graph_seal.to_secret_seal().as_reduced_unsafe());
-
-            let invoice = RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
-                .set_contract(contract_id)
-                .set_interface(iface)
-                .set_amount_raw(value)
-                .finish();
-            println!("{invoice}");
-            Ok(JsonNull)
+            let seal = bdk::bitcoin::OutPoint::from_str(&seal).map_err(|_| {
+                RuntimeError::usage(format!("invalid seal '{seal}', expected TXID:VOUT"))
+            })?;
+            let invoice = service.invoice(InvoiceRequest {
+                contract_id,
+                iface,
+                amount: value,
+                seal,
+                proxy,
+            })?;
+            Ok(serde_json::to_value(invoice)?)
         }
+        RgbSubCommand::Send {
+            invoice,
+            file,
+            proxy,
+            wait,
+            timeout,
+        } => send(
+            &invoice,
+            proxy.as_deref(),
+            &file,
+            wait,
+            std::time::Duration::from_secs(timeout),
+        ),
+        RgbSubCommand::Receive {
+            invoice,
+            proxy,
+            queue,
+            out,
+        } => receive(service, &invoice, proxy.as_deref(), queue, out.as_deref()),
+    }
+}
+
+/// Validates a newly issued contract, adds it to the stash and optionally
+/// exports it to a file.
+fn import_issued(
+    service: &mut RgbService,
+    contract: Contract,
+    out: Option<PathBuf>,
+) -> Result<JsonValue, anyhow::Error> {
+    let summary = service.issue(contract)?;
+    let id = summary.contract_id;
+    eprintln!("A new contract {id} is issued and added to the stash.");
+    if let Some(out) = &out {
+        service.export(id)?.save(out)?;
+        eprintln!("The contract is exported to {}", out.display());
     }
+    let mut summary = serde_json::to_value(summary)?;
+    summary["file"] = json!(out);
+    Ok(summary)
 }
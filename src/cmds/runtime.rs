@@ -22,7 +22,10 @@
 use rgbfs::StockFs;
 use rgbstd::containers::{Contract, LoadError, Transfer};
 use rgbstd::interface::{BuilderError, OutpointFilter};
-use rgbstd::persistence::{Inventory, InventoryDataError, InventoryError, StashError, Stock};
+use rgbstd::persistence::{
+    Inventory, InventoryDataError, InventoryError, Stash, StashError, Stock,
+};
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::io;
 use std::ops::{Deref, DerefMut};
@@ -30,11 +33,13 @@ use std::path::PathBuf;
 
 use rgbstd::resolvers::ResolveHeight;
 use rgbstd::validation;
-use rgbstd::XOutpoint;
+use rgbstd::{ContractId, Outpoint, XChain, XOutpoint};
 
 use strict_types::encoding::{DeserializeError, Ident, SerializeError};
 
 use amplify::{Display, Error, From, Getters};
+use psbt::{CommitError, EmbedError, RgbPsbtError};
+use rgb_rt::ResolverError;
 
 #[derive(Debug, Display, Error, From)]
 #[display(inner)]
@@ -57,9 +62,12 @@ pub enum RuntimeError {
     #[from]
     Stash(StashError<Infallible>),
 
-    #[from]
-    #[from(InventoryDataError<Infallible>)]
-    Inventory(InventoryError<Infallible>),
+    /// Inventory failure. Inventory errors can't be sent between threads, so
+    /// only their message is kept.
+    Inventory(String),
+
+    /// Consignment data rejected by the inventory.
+    InventoryData(String),
 
     #[from]
     Builder(BuilderError),
@@ -71,23 +79,98 @@ pub enum RuntimeError {
     #[from]
     Psbt(bitcoin::psbt::Error),
 
-    #[cfg(feature = "electrum")]
     #[from]
-    Electrum(electrum_client::Error),
+    WalletPsbt(bdk::bitcoin::psbt::Error),
+
+    #[from]
+    PsbtDecode(psbt::DecodeError),
+
+    PsbtRgb(Box<RgbPsbtError>),
+
+    #[from]
+    Embed(EmbedError),
+
+    Commit(Box<CommitError>),
+
+    #[from]
+    Wallet(bdk::Error),
+
+    #[from]
+    Electrum(bdk::electrum_client::Error),
+
+    Resolver(Box<ResolverError>),
+
+    #[from]
+    Json(serde_json::Error),
 
     #[from]
     InvalidConsignment(validation::Status),
 
+    #[display("unable to resolve witness transactions\n{0}")]
+    UnresolvedWitnesses(validation::Status),
+
+    /// the consignment can't be decoded: {0}
+    #[display(doc_comments)]
+    MalformedConsignment(LoadError),
+
     /// the contract source doesn't provide all state information required by
     /// the schema. This means that some of the global fields or assignments are
     /// missed.
     #[display(doc_comments)]
     IncompleteContract,
 
+    /// Invalid arguments or input files.
+    Usage(String),
+
+    /// Electrum, esplora or proxy server failure.
+    Network(String),
+
+    /// The operation is not supported yet.
+    Unsupported(String),
+
+    /// Failure which is a bug of the tool.
+    Internal(String),
+
+    /// Missing or inconsistent data in the RGB stash.
     #[from]
     Custom(String),
 }
 
+impl From<RgbPsbtError> for RuntimeError {
+    fn from(err: RgbPsbtError) -> Self {
+        RuntimeError::PsbtRgb(Box::new(err))
+    }
+}
+
+impl From<CommitError> for RuntimeError {
+    fn from(err: CommitError) -> Self {
+        RuntimeError::Commit(Box::new(err))
+    }
+}
+
+impl From<ResolverError> for RuntimeError {
+    fn from(err: ResolverError) -> Self {
+        RuntimeError::Resolver(Box::new(err))
+    }
+}
+
+impl From<InventoryError<Infallible>> for RuntimeError {
+    fn from(err: InventoryError<Infallible>) -> Self {
+        match err {
+            InventoryError::DataError(_) | InventoryError::Consume(_) => {
+                RuntimeError::InventoryData(err.to_string())
+            }
+            _ => RuntimeError::Inventory(err.to_string()),
+        }
+    }
+}
+
+impl From<InventoryDataError<Infallible>> for RuntimeError {
+    fn from(err: InventoryDataError<Infallible>) -> Self {
+        RuntimeError::from(InventoryError::from(err))
+    }
+}
+
 impl From<Infallible> for RuntimeError {
     fn from(_: Infallible) -> Self {
         unreachable!()
@@ -98,6 +181,8 @@ impl From<Infallible> for RuntimeError {
 pub struct Runtime {
     #[getter(skip)]
     stock: Stock,
+    #[getter(as_ref)]
+    stock_path: PathBuf,
 }
 
 impl Deref for Runtime {
@@ -125,30 +210,61 @@ impl Runtime {
         chain: &bdk::bitcoin::Network,
     ) -> Result<Self, RuntimeError> {
         data_dir.push(chain.to_string());
-        #[cfg(feature = "log")]
-        debug!("Using data directory '{}'", data_dir.display());
+        log::debug!("Using data directory '{}'", data_dir.display());
         fs::create_dir_all(&data_dir)?;
 
         let mut stock_path = data_dir.clone();
         stock_path.push("stock.dat");
-        #[cfg(feature = "log")]
-        debug!("Reading stock from '{}'", stock_path.display());
+        log::debug!("Reading stock from '{}'", stock_path.display());
         let stock = if !stock_path.exists() {
-            #[cfg(feature = "log")]
-            info!("Stock file not found, creating default stock");
-            #[cfg(feature = "cli")]
-            eprintln!("Stock file not found, creating default stock");
+            log::info!("Stock file not found, creating default stock");
             let stock = Stock::default();
             stock.store(&stock_path)?;
             stock
         } else {
-            Stock::load(&stock_path)?
+            Stock::load(&stock_path).map_err(|err| {
+                RuntimeError::Custom(format!(
+                    "unable to read stock '{}': {err}; the stash is corrupted, restore it from a \
+                     backup or merge it from another stock",
+                    stock_path.display()
+                ))
+            })?
         };
 
-        let mut wallets_path = data_dir.clone();
-        wallets_path.push("wallets.yml");
+        Ok(Self { stock, stock_path })
+    }
 
-        Ok(Self { stock })
+    /// Loads the stock of an existing stash, failing instead of creating an
+    /// empty stock when the data directory has none.
+    pub fn load_existing(
+        data_dir: PathBuf,
+        chain: &bdk::bitcoin::Network,
+    ) -> Result<Self, RuntimeError> {
+        let stock_path = data_dir.join(chain.to_string()).join("stock.dat");
+        if !stock_path.exists() {
+            return Err(RuntimeError::Custom(format!(
+                "no RGB stash found at '{}'",
+                stock_path.display()
+            )));
+        }
+        Self::load(data_dir, chain)
+    }
+
+    /// Saves the stock back to the data directory it was loaded from.
+    ///
+    /// The stock is written to a temporary file first and then moved over the
+    /// existing one, so an interrupted write never leaves a truncated stock.
+    pub fn store(&self) -> Result<(), RuntimeError> {
+        log::debug!("Saving stock to '{}'", self.stock_path.display());
+        let tmp_path = self.stock_path.with_extension("dat.tmp");
+        self.stock.store(&tmp_path)?;
+        fs::rename(&tmp_path, &self.stock_path)?;
+        Ok(())
+    }
+
+    /// Replaces the in-memory stock, returning the previous one.
+    pub fn replace_stock(&mut self, stock: Stock) -> Stock {
+        std::mem::replace(&mut self.stock, stock)
     }
 
     pub fn import_contract<R: ResolveHeight>(
@@ -179,13 +295,29 @@ impl Runtime {
     }
 }
 
-impl Drop for Runtime {
-    fn drop(&mut self) {
-        // self.stock
-        //     .store(&self.stock_path)
-        //     .expect("unable to save stock");
-        // let wallets_fd = File::create(&self.wallets_path)
-        //     .expect("unable to access wallet file; wallets are not saved");
-        // serde_yaml::to_writer(wallets_fd, &self.wallets).expect("unable to save wallets");
+impl Runtime {
+    /// Returns the contracts which assign state to each of the given outpoints.
+    ///
+    /// Outpoints without any known RGB state are omitted from the result.
+    pub fn contracts_by_outpoints(
+        &self,
+        outpoints: impl IntoIterator<Item = Outpoint>,
+    ) -> Result<BTreeMap<Outpoint, BTreeSet<ContractId>>, RuntimeError> {
+        let outpoints = outpoints
+            .into_iter()
+            .map(XChain::Bitcoin)
+            .collect::<Vec<XOutpoint>>();
+        let mut contracts = BTreeMap::<Outpoint, BTreeSet<ContractId>>::new();
+        for contract_id in self.stock.contract_ids()? {
+            let state = self
+                .stock
+                .state_for_outpoints(contract_id, outpoints.iter().copied())?;
+            for (_, seal) in state.keys() {
+                if let XChain::Bitcoin(outpoint) = XOutpoint::from(*seal) {
+                    contracts.entry(outpoint).or_default().insert(contract_id);
+                }
+            }
+        }
+        Ok(contracts)
     }
 }
@@ -0,0 +1,242 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use bdk::bitcoin::Network;
+use clap::{Args, ValueHint};
+use psbt::PsbtVer;
+use rgbstd::contract::ContractId;
+use serde_crate::Deserialize;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::session::{Session, SessionCommand, SessionOpts};
+use crate::service::{InvoiceRequest, StateRequest};
+
+/// Options of the JSON-RPC server
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct ServeOpts {
+    /// Loopback TCP address to listen on, like 127.0.0.1:3000
+    #[clap(long, required_unless_present = "unix", conflicts_with = "unix")]
+    pub tcp: Option<SocketAddr>,
+
+    /// Unix socket to listen on
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    pub unix: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub session: SessionOpts,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RpcRequest {
+    #[serde(default)]
+    id: JsonValue,
+    method: String,
+    #[serde(default)]
+    params: JsonValue,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ArgsParams {
+    args: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ContractParams {
+    contract_id: String,
+}
+
+/// Errors returned to the JSON-RPC clients
+enum RpcError {
+    Parse(String),
+    InvalidRequest(String),
+    MethodNotFound(String),
+    InvalidParams(String),
+    /// Failure of the command, with the same kind and exit code as reported
+    /// by the command line tool.
+    Command(Box<RuntimeError>),
+}
+
+impl RpcError {
+    fn to_json(&self) -> JsonValue {
+        let (code, message) = match self {
+            RpcError::Parse(msg) => (-32700, msg.clone()),
+            RpcError::InvalidRequest(msg) => (-32600, msg.clone()),
+            RpcError::MethodNotFound(method) => (-32601, format!("unknown method '{method}'")),
+            RpcError::InvalidParams(msg) => (-32602, msg.clone()),
+            RpcError::Command(err) => {
+                return json!({
+                    "code": -32000,
+                    "message": err.to_string(),
+                    "data": {
+                        "kind": err.kind().to_string(),
+                        "exit_code": err.exit_code(),
+                    },
+                })
+            }
+        };
+        json!({ "code": code, "message": message })
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        RpcError::Command(Box::new(RuntimeError::classify(err)))
+    }
+}
+
+impl From<RuntimeError> for RpcError {
+    fn from(err: RuntimeError) -> Self {
+        RpcError::Command(Box::new(err))
+    }
+}
+
+/// Runs the JSON-RPC server until the process is stopped.
+///
+/// Requests and responses are JSON-RPC 2.0 objects, one per line. Besides
+/// the typed methods, `rgb`, `wallet` and `key` methods take the arguments of
+/// the corresponding command in the `args` parameter.
+///
+/// Requests are not authenticated and may sign with the wallets or read the
+/// keys, so TCP connections are only accepted on a loopback address.
+pub fn handle_serve(
+    network: Network,
+    psbt_version: Option<PsbtVer>,
+    opts: ServeOpts,
+) -> Result<JsonValue, anyhow::Error> {
+    if let Some(addr) = opts.tcp.filter(|addr| !addr.ip().is_loopback()) {
+        return Err(RuntimeError::usage(format!(
+            "refusing to listen on {addr}: the server has no authentication, use a loopback \
+             address like 127.0.0.1 or a unix socket"
+        ))
+        .into());
+    }
+    let session = Arc::new(Session::open(network, psbt_version, opts.session)?);
+
+    #[cfg(not(unix))]
+    if opts.unix.is_some() {
+        return Err(RuntimeError::unsupported("unix sockets on this platform").into());
+    }
+    #[cfg(unix)]
+    if let Some(path) = opts.unix {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+
+        // Socket left by a previous run which was not stopped cleanly
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        eprintln!("Listening for JSON-RPC requests on {}", path.display());
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let reader = BufReader::new(stream.try_clone()?);
+            let session = session.clone();
+            thread::spawn(move || serve_connection(&session, reader, stream));
+        }
+        return Ok(JsonValue::Null);
+    }
+
+    let addr = opts
+        .tcp
+        .ok_or_else(|| RuntimeError::usage("either --tcp or --unix must be given"))?;
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Listening for JSON-RPC requests on {addr}");
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let session = session.clone();
+        thread::spawn(move || serve_connection(&session, reader, stream));
+    }
+    Ok(JsonValue::Null)
+}
+
+fn serve_connection(session: &Session, reader: impl BufRead, mut writer: impl Write) {
+    for line in reader.lines() {
+        let Ok(line) = line else { return };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(session, &line);
+        if writeln!(writer, "{response}")
+            .and_then(|_| writer.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+fn handle_line(session: &Session, line: &str) -> JsonValue {
+    let request = match serde_json::from_str::<JsonValue>(line) {
+        Ok(request) => request,
+        Err(err) => return response(JsonValue::Null, Err(RpcError::Parse(err.to_string()))),
+    };
+    let id = request.get("id").cloned().unwrap_or_default();
+    let request = match serde_json::from_value::<RpcRequest>(request) {
+        Ok(request) => request,
+        Err(err) => return response(id, Err(RpcError::InvalidRequest(err.to_string()))),
+    };
+    log::debug!("JSON-RPC request {}: {}", request.id, request.method);
+    let result = call(session, &request.method, request.params);
+    response(request.id, result)
+}
+
+fn call(session: &Session, method: &str, params: JsonValue) -> Result<JsonValue, RpcError> {
+    match method {
+        "rgb" | "wallet" | "key" => {
+            let params = parse_params::<ArgsParams>(params)?;
+            let args = [method.to_owned()].into_iter().chain(params.args);
+            let command = SessionCommand::parse_args(args)
+                .map_err(|err| RpcError::InvalidParams(err.render().to_string()))?;
+            Ok(session.run(command)?)
+        }
+        "rgb.schemata" => Ok(to_json(session.rgb().schemata()?)?),
+        "rgb.interfaces" => Ok(to_json(session.rgb().interfaces()?)?),
+        "rgb.contracts" => {
+            let ids = session.rgb().contracts()?;
+            Ok(json!(ids
+                .iter()
+                .map(ContractId::to_string)
+                .collect::<Vec<_>>()))
+        }
+        "rgb.contract" => {
+            let params = parse_params::<ContractParams>(params)?;
+            let contract_id = params
+                .contract_id
+                .parse::<ContractId>()
+                .map_err(|err| RpcError::InvalidParams(format!("invalid contract id: {err}")))?;
+            Ok(to_json(session.rgb().contract(contract_id)?)?)
+        }
+        "rgb.state" => {
+            let request = parse_params::<StateRequest>(params)?;
+            Ok(to_json(session.rgb().state(request)?)?)
+        }
+        "rgb.invoice" => {
+            let request = parse_params::<InvoiceRequest>(params)?;
+            Ok(to_json(session.rgb_mut().invoice(request)?)?)
+        }
+        _ => Err(RpcError::MethodNotFound(method.to_owned())),
+    }
+}
+
+fn response(id: JsonValue, result: Result<JsonValue, RpcError>) -> JsonValue {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() }),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: JsonValue) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::InvalidParams(err.to_string()))
+}
+
+fn to_json(value: impl serde_crate::Serialize) -> Result<JsonValue, anyhow::Error> {
+    Ok(serde_json::to_value(value)?)
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bdk::bitcoin::Network;
+use bdk::blockchain::AnyBlockchain;
+use bdk::database::AnyDatabase;
+use clap::{Args, Parser, Subcommand, ValueHint};
+use psbt::PsbtVer;
+use serde_json::Value as JsonValue;
+
+use crate::cmds::key::{handle_key_subcommand, KeySubCommand};
+use crate::cmds::rgb::{rgb_electrum, rgb_esplora, run_rgb_subcommand, RgbSubCommand};
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::wallet::{handle_offline_wallet_subcommand, handle_online_wallet_subcommand};
+use crate::opts::{WalletOpts, WalletSubCommand, RGB_DATA_DIR};
+use crate::service::{RgbService, WalletService};
+use crate::utils::maybe_descriptor_wallet_name;
+
+/// Options of the commands which keep the stash and the wallets open
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct SessionOpts {
+    /// Data directory path.
+    ///
+    /// Path to the directory that contains RGB stored data.
+    #[clap(
+        short = 'd',
+        long,
+        default_value = RGB_DATA_DIR,
+        value_hint = ValueHint::DirPath
+    )]
+    pub data_dir: PathBuf,
+
+    /// Electrum server used by the RGB commands.
+    #[clap(short = 's', long)]
+    pub electrum: Option<String>,
+
+    /// Esplora server resolving the witness transactions of the RGB commands.
+    #[clap(long)]
+    pub esplora: Option<String>,
+
+    /// Sets the wallet data directory.
+    /// Default value : "~/.bdk-bitcoin
+    #[clap(long = "walletdir", value_hint = ValueHint::DirPath)]
+    pub wallet_dir: Option<PathBuf>,
+}
+
+/// Command run in a session, with the same arguments as on the command line
+/// but without the data directories and the servers of the stash.
+#[derive(Subcommand, Debug)]
+pub enum SessionCommand {
+    /// RGB operations
+    Rgb {
+        #[clap(subcommand)]
+        subcommand: RgbSubCommand,
+    },
+
+    /// Wallet operations.
+    Wallet {
+        #[clap(flatten)]
+        wallet_opts: WalletOpts,
+        #[clap(subcommand)]
+        subcommand: WalletSubCommand,
+    },
+
+    /// Execute key commands.
+    Key {
+        #[clap(subcommand)]
+        subcommand: KeySubCommand,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "bihelix-rgb-cli", no_binary_name = true)]
+struct SessionArgs {
+    #[clap(subcommand)]
+    command: SessionCommand,
+}
+
+impl SessionCommand {
+    /// Parses the arguments of a command, starting with `rgb`, `wallet` or `key`.
+    pub fn parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        SessionArgs::try_parse_from(args).map(|args| args.command)
+    }
+}
+
+/// Wallet kept open between the commands
+struct OpenWallet {
+    service: WalletService<AnyDatabase>,
+    blockchain: Option<AnyBlockchain>,
+}
+
+/// Open wallet with the descriptor and the change descriptor its database is
+/// bound to
+struct CachedWallet {
+    descriptors: (String, Option<String>),
+    wallet: Arc<Mutex<OpenWallet>>,
+}
+
+/// Stash, wallets and server connections shared by the commands of a
+/// session.
+///
+/// The typed queries of the library only take the read lock and run in
+/// parallel. Command line commands of the stash always take the write lock,
+/// as they may change it, so they run one at a time.
+pub struct Session {
+    network: Network,
+    psbt_version: Option<PsbtVer>,
+    electrum: String,
+    esplora: String,
+    wallet_dir: Option<PathBuf>,
+    rgb: RwLock<RgbService>,
+    wallets: Mutex<BTreeMap<String, CachedWallet>>,
+}
+
+impl Session {
+    pub fn open(
+        network: Network,
+        psbt_version: Option<PsbtVer>,
+        opts: SessionOpts,
+    ) -> Result<Self, anyhow::Error> {
+        let electrum = rgb_electrum(opts.electrum, &network)?;
+        let esplora = rgb_esplora(opts.esplora, &network)?;
+        let rgb = RgbService::load(opts.data_dir, network, &esplora)?;
+        Ok(Session {
+            network,
+            psbt_version,
+            electrum,
+            esplora,
+            wallet_dir: opts.wallet_dir,
+            rgb: RwLock::new(rgb),
+            wallets: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Stash for the typed queries, which may run in parallel.
+    pub fn rgb(&self) -> RwLockReadGuard<'_, RgbService> {
+        self.rgb.read().expect("poisoned stash lock")
+    }
+
+    /// Stash for commands changing it, which run one at a time.
+    pub fn rgb_mut(&self) -> RwLockWriteGuard<'_, RgbService> {
+        self.rgb.write().expect("poisoned stash lock")
+    }
+
+    pub fn run(&self, command: SessionCommand) -> Result<JsonValue, anyhow::Error> {
+        match command {
+            // Watching never returns and would hold the stash until the
+            // session ends
+            SessionCommand::Rgb {
+                subcommand: RgbSubCommand::Watch { once: false, .. },
+            } => Err(RuntimeError::usage(
+                "rgb watch runs until it is stopped, use --once in a session",
+            )
+            .into()),
+            SessionCommand::Rgb { subcommand } => run_rgb_subcommand(
+                &mut self.rgb_mut(),
+                &self.network,
+                &self.electrum,
+                &self.esplora,
+                self.psbt_version,
+                subcommand,
+            ),
+            SessionCommand::Wallet {
+                wallet_opts,
+                subcommand,
+            } => self.run_wallet(wallet_opts, subcommand),
+            SessionCommand::Key { subcommand } => handle_key_subcommand(self.network, subcommand),
+        }
+    }
+
+    fn run_wallet(
+        &self,
+        wallet_opts: WalletOpts,
+        subcommand: WalletSubCommand,
+    ) -> Result<JsonValue, anyhow::Error> {
+        let wallet_opts = maybe_descriptor_wallet_name(wallet_opts, self.network)?;
+        let Some(name) = wallet_opts.wallet.clone() else {
+            return Err(RuntimeError::usage("wallet name is missing").into());
+        };
+        let descriptors = (
+            wallet_opts.descriptor.clone(),
+            wallet_opts.change_descriptor.clone(),
+        );
+        let wallet = {
+            let mut wallets = self.wallets.lock().expect("poisoned wallets lock");
+            match wallets.get(&name) {
+                Some(cached) if cached.descriptors != descriptors => {
+                    return Err(RuntimeError::usage(format!(
+                        "wallet '{name}' is already open with other descriptors"
+                    ))
+                    .into());
+                }
+                Some(cached) => cached.wallet.clone(),
+                None => {
+                    let service =
+                        WalletService::open(self.network, &wallet_opts, self.wallet_dir.clone())?;
+                    let wallet = Arc::new(Mutex::new(OpenWallet {
+                        service,
+                        blockchain: None,
+                    }));
+                    wallets.insert(
+                        name,
+                        CachedWallet {
+                            descriptors,
+                            wallet: wallet.clone(),
+                        },
+                    );
+                    wallet
+                }
+            }
+        };
+
+        let mut wallet = wallet.lock().expect("poisoned wallet lock");
+        match subcommand {
+            WalletSubCommand::OnlineWalletSubCommand(subcommand) => {
+                if wallet.blockchain.is_none() {
+                    wallet.blockchain = Some(WalletService::connect(&wallet_opts)?);
+                }
+                let OpenWallet {
+                    service,
+                    blockchain,
+                } = &*wallet;
+                let blockchain = blockchain.as_ref().expect("connected above");
+                handle_online_wallet_subcommand(service, blockchain, subcommand)
+            }
+            WalletSubCommand::OfflineWalletSubCommand(subcommand) => {
+                handle_offline_wallet_subcommand(
+                    &wallet.service,
+                    &wallet_opts,
+                    self.psbt_version,
+                    subcommand,
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bdk::bitcoin::Network;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueHint};
+use psbt::PsbtVer;
+use rgbstd::contract::ContractId;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::Value as JsonValue;
+
+use crate::cmds::batch::split_args;
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::session::{Session, SessionCommand, SessionOpts};
+use crate::opts::{OutputFormat, WalletOpts};
+use crate::output::render;
+use crate::rgb_chain;
+
+const HISTORY_FILE: &str = ".bihelix-rgb-cli_history";
+
+/// Options of the lines which are not written to the history file, since
+/// their values may hold private keys.
+const SECRET_OPTIONS: [&str; 4] = ["-d", "--descriptor", "-c", "--change_descriptor"];
+
+/// Prefixes of the extended private keys.
+const XPRV_PREFIXES: [&str; 6] = ["xprv", "tprv", "yprv", "zprv", "uprv", "vprv"];
+
+/// Argument replaced with the contract selected with `use contract`.
+const CONTRACT_VAR: &str = "$contract";
+
+#[derive(Parser, Debug)]
+#[command(name = "", no_binary_name = true)]
+struct ShellArgs {
+    #[clap(subcommand)]
+    command: ShellCommand,
+}
+
+/// Commands of the interactive shell
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
+enum ShellCommand {
+    #[clap(flatten)]
+    Session(SessionCommand),
+
+    /// Selects the network, the data directory, the wallet or the contract
+    /// used by the next commands.
+    Use {
+        #[clap(subcommand)]
+        target: UseTarget,
+    },
+
+    /// Prints the selected network, data directory, wallet and contract.
+    Context,
+
+    /// Leaves the shell.
+    #[clap(alias = "quit")]
+    Exit,
+}
+
+#[derive(Subcommand, Debug)]
+enum UseTarget {
+    /// Reopens the stash for another network. bitcoin|testnet|regtest|signet.
+    Network { network: Network },
+
+    /// Reopens the stash from another data directory.
+    DataDir {
+        #[clap(value_hint = ValueHint::DirPath)]
+        data_dir: PathBuf,
+    },
+
+    /// Wallet options added to the `wallet` commands which don't give a
+    /// descriptor, e.g. `use wallet -w main -d <descriptor>`.
+    Wallet {
+        #[clap(flatten)]
+        wallet_opts: WalletOpts,
+    },
+
+    /// Contract replacing `$contract` in the arguments of the next commands.
+    Contract { contract_id: ContractId },
+}
+
+/// State kept between the commands of the shell
+struct ShellContext {
+    session: Session,
+    opts: SessionOpts,
+    psbt_version: Option<PsbtVer>,
+    /// Arguments of `use wallet`, as typed.
+    wallet_args: Vec<String>,
+    contract: Option<ContractId>,
+}
+
+/// Runs an interactive shell reading the commands of a [`Session`].
+///
+/// Commands take the same arguments as in batch mode. `use` keeps the
+/// network, the data directory, a wallet and a contract selected between the
+/// commands, contract ids and interface names of the stash are completed with
+/// tab, and the history is saved in the home directory.
+pub fn handle_shell(
+    network: Network,
+    psbt_version: Option<PsbtVer>,
+    output: OutputFormat,
+    json_errors: bool,
+    opts: SessionOpts,
+) -> Result<JsonValue, anyhow::Error> {
+    let session = Session::open(network, psbt_version, opts.clone())?;
+    let mut context = ShellContext {
+        session,
+        opts,
+        psbt_version,
+        wallet_args: vec![],
+        contract: None,
+    };
+
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ShellHelper {
+        command: ShellArgs::command(),
+        words: vec![],
+    }));
+    let history = dirs_next::home_dir().map(|dir| dir.join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // There is no history yet on the first run
+        let _ = editor.load_history(history);
+    }
+
+    eprintln!("Type `help` for the list of commands, `exit` to leave.");
+    loop {
+        if let Some(helper) = editor.helper_mut() {
+            helper.words = context.completion_words();
+        }
+        let line = match editor.readline(&context.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !has_secrets(&line) {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        match context.run_line(&line) {
+            Ok(None) => break,
+            Ok(Some(JsonValue::Null)) => {}
+            Ok(Some(value)) => match render(&value, output) {
+                Ok(out) => println!("{out}"),
+                Err(err) => report_error(RuntimeError::classify(err), json_errors),
+            },
+            Err(err) => report_error(err, json_errors),
+        }
+    }
+
+    if let Some(history) = &history {
+        create_private(history)?;
+        editor.save_history(history)?;
+    }
+    Ok(JsonValue::Null)
+}
+
+/// Whether a line may carry key material: `key` commands, descriptors of the
+/// wallets or extended private keys.
+fn has_secrets(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    if words.next() == Some("key") {
+        return true;
+    }
+    words.any(|word| {
+        SECRET_OPTIONS
+            .iter()
+            .any(|option| word == *option || word.starts_with(&format!("{option}=")))
+            || XPRV_PREFIXES.iter().any(|prefix| word.contains(prefix))
+    })
+}
+
+/// Creates the history file readable by its owner only, or restricts the
+/// permissions of an existing one.
+#[cfg(unix)]
+fn create_private(path: &Path) -> Result<(), io::Error> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn create_private(_path: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
+
+impl ShellContext {
+    fn prompt(&self) -> String {
+        let mut prompt = self.session.network().to_string();
+        if let Some(name) = self.wallet_name() {
+            prompt.push(':');
+            prompt.push_str(&name);
+        }
+        prompt + "> "
+    }
+
+    fn wallet_name(&self) -> Option<String> {
+        if self.wallet_args.is_empty() {
+            return None;
+        }
+        let wallet_opts = parse_wallet_args(&self.wallet_args).ok()?;
+        Some(wallet_opts.wallet.unwrap_or_else(|| "wallet".to_owned()))
+    }
+
+    /// Contract ids and interface names of the stash.
+    fn completion_words(&self) -> Vec<String> {
+        let rgb = self.session.rgb();
+        let contracts = rgb.contracts().unwrap_or_default();
+        let ifaces = rgb.interfaces().unwrap_or_default();
+        contracts
+            .iter()
+            .map(ContractId::to_string)
+            .chain(ifaces.into_iter().map(|iface| iface.name))
+            .chain([CONTRACT_VAR.to_owned()])
+            .collect()
+    }
+
+    /// Runs a command line, returning `None` when the shell should be left.
+    fn run_line(&mut self, line: &str) -> Result<Option<JsonValue>, RuntimeError> {
+        let args = self.expand_args(split_args(line)?)?;
+        let command = match ShellArgs::try_parse_from(&args) {
+            Ok(args) => args.command,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+                ) =>
+            {
+                print!("{}", err.render());
+                return Ok(Some(JsonValue::Null));
+            }
+            Err(err) => return Err(RuntimeError::usage(err.render().to_string().trim_end())),
+        };
+
+        let value = match command {
+            ShellCommand::Session(command) => {
+                self.session.run(command).map_err(RuntimeError::classify)?
+            }
+            ShellCommand::Use { target } => {
+                self.select(target, &args[2..])?;
+                self.describe()
+            }
+            ShellCommand::Context => self.describe(),
+            ShellCommand::Exit => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+
+    /// Replaces `$contract` and adds the selected wallet options.
+    fn expand_args(&self, mut args: Vec<String>) -> Result<Vec<String>, RuntimeError> {
+        for arg in args.iter_mut().filter(|arg| arg.contains(CONTRACT_VAR)) {
+            let contract_id = self.contract.ok_or_else(|| {
+                RuntimeError::usage("no contract selected, run `use contract <id>`")
+            })?;
+            *arg = arg.replace(CONTRACT_VAR, &contract_id.to_string());
+        }
+
+        let gives_wallet = args.iter().any(|arg| {
+            matches!(arg.as_str(), "-d" | "--descriptor" | "-w" | "--wallet")
+                || arg.starts_with("--descriptor=")
+                || arg.starts_with("--wallet=")
+        });
+        if args.first().map(String::as_str) == Some("wallet") && !gives_wallet {
+            args.splice(1..1, self.wallet_args.iter().cloned());
+        }
+        Ok(args)
+    }
+
+    fn select(&mut self, target: UseTarget, args: &[String]) -> Result<(), RuntimeError> {
+        match target {
+            UseTarget::Network { network } => {
+                let network = rgb_chain(network)?;
+                self.reopen(network, self.opts.clone())
+            }
+            UseTarget::DataDir { data_dir } => {
+                let opts = SessionOpts {
+                    data_dir,
+                    ..self.opts.clone()
+                };
+                self.reopen(self.session.network(), opts)
+            }
+            UseTarget::Wallet { .. } => {
+                self.wallet_args = args.to_vec();
+                Ok(())
+            }
+            UseTarget::Contract { contract_id } => {
+                self.contract = Some(contract_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Opens the stash again, keeping the current one if that fails.
+    fn reopen(&mut self, network: Network, opts: SessionOpts) -> Result<(), RuntimeError> {
+        self.session = Session::open(network, self.psbt_version, opts.clone())
+            .map_err(RuntimeError::classify)?;
+        self.opts = opts;
+        Ok(())
+    }
+
+    fn describe(&self) -> JsonValue {
+        serde_json::json!({
+            "network": self.session.network().to_string(),
+            "data_dir": self.opts.data_dir,
+            "wallet": self.wallet_name(),
+            "contract": self.contract.map(|id| id.to_string()),
+        })
+    }
+}
+
+fn parse_wallet_args(args: &[String]) -> Result<WalletOpts, clap::Error> {
+    #[derive(Parser)]
+    #[command(no_binary_name = true)]
+    struct Args {
+        #[clap(flatten)]
+        wallet_opts: WalletOpts,
+    }
+    Args::try_parse_from(args).map(|args| args.wallet_opts)
+}
+
+fn report_error(err: RuntimeError, json: bool) {
+    if json {
+        eprintln!("{}", err.to_json());
+    } else {
+        eprintln!("ERROR: {}", err);
+    }
+}
+
+/// Completes the subcommands and the long options from the clap definitions,
+/// and the contract ids and the interface names from the stash.
+struct ShellHelper {
+    command: clap::Command,
+    words: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line
+            .rfind(char::is_whitespace)
+            .map(|idx| idx + 1)
+            .unwrap_or_default();
+        let word = &line[start..];
+        let Ok(previous) = split_args(&line[..start]) else {
+            return Ok((pos, vec![]));
+        };
+
+        let mut command = &self.command;
+        for arg in &previous {
+            if let Some(subcommand) = command.find_subcommand(arg) {
+                command = subcommand;
+            }
+        }
+
+        let candidates = if word.starts_with('-') {
+            command
+                .get_arguments()
+                .filter_map(|arg| arg.get_long())
+                .map(|long| format!("--{long}"))
+                .filter(|long| long.starts_with(word))
+                .collect()
+        } else {
+            command
+                .get_subcommands()
+                .map(|subcommand| subcommand.get_name().to_owned())
+                .chain(self.words.iter().cloned())
+                .filter(|candidate| candidate.starts_with(word))
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
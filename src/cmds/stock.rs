@@ -0,0 +1,527 @@
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+
+use rgb::validation::{ResolveWitness, Validity, WitnessResolverError};
+use rgb::{AnchoredBundle, WitnessAnchor, WitnessId, XAnchor, XPubWitness};
+use rgbstd::containers::Contract;
+use rgbstd::contract::ContractId;
+use rgbstd::interface::IfacePair;
+use rgbstd::persistence::{Inventory, Stash, Stock};
+use rgbstd::resolvers::ResolveHeight;
+use rgbstd::Operation;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::runtime::Runtime;
+use crate::cmds::runtime::RuntimeError;
+
+/// Imports everything known to `other` into the runtime stock.
+///
+/// Schemata, interfaces and seal secrets are content-addressed, so they are
+/// either new or already known. An interface implementation differing from
+/// the one we already have for the same schema and interface is reported as a
+/// conflict and our own is kept. Contracts are taken from `other` with their
+/// whole known history and validated before being merged; invalid ones
+/// are reported as conflicts. Histories with unmined or unresolved witness
+/// transactions are only merged when `force` is set. Seal secrets are stored
+/// last, so a stock failing validation leaves none of them behind. The runtime
+/// stock is only replaced once the whole merge succeeds.
+pub fn merge_stock<R>(
+    runtime: &mut Runtime,
+    other: &Stock,
+    resolver: &mut R,
+    force: bool,
+) -> Result<JsonValue, anyhow::Error>
+where
+    R: ResolveHeight + ResolveWitness,
+    R::Error: 'static,
+{
+    let mut stock = Stock::clone(runtime);
+    let mut added = json!({
+        "schemata": [],
+        "interfaces": [],
+        "implementations": [],
+        "contracts": [],
+        "seal_secrets": 0,
+    });
+    let mut conflicts = vec![];
+
+    let known_ifaces = stock.ifaces()?;
+    for (id, name) in other.ifaces()? {
+        if known_ifaces.contains_key(&id) {
+            continue;
+        }
+        stock
+            .import_iface(other.iface_by_id(id)?.clone())
+            .map_err(RuntimeError::from)?;
+        added["interfaces"]
+            .as_array_mut()
+            .expect("array")
+            .push(json!({ "id": id.to_string(), "name": name.to_string() }));
+    }
+
+    let known_schemata = stock.schema_ids()?;
+    let mut conflicting_impls = BTreeSet::new();
+    for schema_id in other.schema_ids()? {
+        let schema_ifaces = other.schema(schema_id)?;
+        if !known_schemata.contains(&schema_id) {
+            stock
+                .import_schema(schema_ifaces.schema.clone())
+                .map_err(RuntimeError::from)?;
+            added["schemata"]
+                .as_array_mut()
+                .expect("array")
+                .push(json!(schema_id.to_string()));
+        }
+        for (iface_id, iimpl) in &schema_ifaces.iimpls {
+            let ours = stock.schema(schema_id)?.iimpls.get(iface_id).cloned();
+            match ours {
+                Some(ours) if ours.impl_id() == iimpl.impl_id() => {}
+                Some(ours) => {
+                    conflicting_impls.insert((schema_id, *iface_id));
+                    conflicts.push(json!({
+                        "kind": "implementation",
+                        "schema_id": schema_id.to_string(),
+                        "iface_id": iface_id.to_string(),
+                        "reason": format!(
+                            "implementation {} differs from the local {}, keeping the local one",
+                            iimpl.impl_id(),
+                            ours.impl_id()
+                        ),
+                    }));
+                }
+                None => {
+                    stock
+                        .import_iface_impl(iimpl.clone())
+                        .map_err(RuntimeError::from)?;
+                    added["implementations"]
+                        .as_array_mut()
+                        .expect("array")
+                        .push(json!({
+                            "id": iimpl.impl_id().to_string(),
+                            "schema_id": schema_id.to_string(),
+                            "iface_id": iface_id.to_string(),
+                        }));
+                }
+            }
+        }
+    }
+
+    let known_contracts = stock.contract_ids()?;
+    let known_bundles = stock.bundle_ids()?;
+    for contract_id in other.contract_ids()? {
+        let mut contract = stash_consignment(other, contract_id)?;
+        // Importing a contract imports the implementations it carries, which
+        // must not replace the conflicting local ones
+        let schema_id = contract.schema.schema_id();
+        for (_, iface_id) in conflicting_impls.iter().filter(|(id, _)| *id == schema_id) {
+            contract.ifaces.remove(iface_id).ok();
+        }
+
+        let contract = match contract.validate(resolver, true) {
+            Ok(contract) => contract,
+            Err(contract)
+                if force
+                    && contract
+                        .validation_status()
+                        .is_some_and(|status| status.validity() != Validity::Invalid) =>
+            {
+                contract
+            }
+            Err(contract) => {
+                conflicts.push(json!({
+                    "kind": "contract",
+                    "contract_id": contract_id.to_string(),
+                    "reason": contract
+                        .validation_status()
+                        .expect("just validated")
+                        .to_string(),
+                }));
+                continue;
+            }
+        };
+        let result = if force {
+            unsafe { stock.import_contract_force(contract, resolver) }
+                .map_err(|err| err.to_string())
+        } else {
+            stock
+                .import_contract(contract, resolver)
+                .map_err(|err| err.to_string())
+        };
+        if let Err(err) = result {
+            conflicts.push(json!({
+                "kind": "contract",
+                "contract_id": contract_id.to_string(),
+                "reason": err,
+            }));
+            continue;
+        }
+
+        let bundles = stock
+            .bundle_ids()?
+            .difference(&known_bundles)
+            .filter_map(|id| stock.bundle(*id).ok())
+            .filter(|bundle| {
+                bundle
+                    .known_transitions
+                    .values()
+                    .any(|transition| transition.contract_id == contract_id)
+            })
+            .count();
+        added["contracts"]
+            .as_array_mut()
+            .expect("array")
+            .push(json!({
+                "contract_id": contract_id.to_string(),
+                "new": !known_contracts.contains(&contract_id),
+                "bundles_added": bundles,
+            }));
+    }
+
+    // Seal secrets are only taken once the contracts of the other stock have
+    // been validated
+    let known_secrets = stock.seal_secrets().map_err(RuntimeError::from)?;
+    let mut secrets_added = 0;
+    for seal in other.seal_secrets().map_err(RuntimeError::from)? {
+        if !known_secrets.contains(&seal) {
+            stock.store_seal_secret(seal).map_err(RuntimeError::from)?;
+            secrets_added += 1;
+        }
+    }
+    added["seal_secrets"] = json!(secrets_added);
+
+    runtime.replace_stock(stock);
+    Ok(json!({
+        "added": added,
+        "conflicts": conflicts,
+    }))
+}
+
+/// Re-validates every contract and cross-checks the inventory indexes against
+/// the stash data.
+///
+/// Reports contracts failing validation, index entries pointing to missing
+/// data (or missing for existing data), transition inputs spending unknown
+/// operations and seal secrets not used by any known assignment. The latter
+/// are expected for invoices which were not paid yet.
+pub fn check_stock<R>(runtime: &Runtime, resolver: &mut R) -> Result<JsonValue, anyhow::Error>
+where
+    R: ResolveWitness,
+{
+    let mut index_issues = vec![];
+    let mut known_ops = BTreeSet::new();
+    let mut seals = BTreeSet::new();
+    let mut inputs = vec![];
+
+    let history = runtime.debug_history();
+    let contract_ids = runtime.contract_ids()?;
+    let mut contracts = vec![];
+    for contract_id in &contract_ids {
+        if !history.contains_key(contract_id) {
+            index_issues.push(format!("contract {contract_id} has no state history"));
+        }
+        if !runtime.debug_contract_index().contains_key(contract_id) {
+            index_issues.push(format!("contract {contract_id} is not indexed"));
+        }
+
+        let genesis = runtime.genesis(*contract_id)?;
+        known_ops.insert(genesis.id());
+        for (_, assigns) in genesis.assignments.iter() {
+            seals.extend(assigns.to_confidential_seals());
+        }
+
+        let status = match stash_consignment(runtime, *contract_id) {
+            Ok(contract) => {
+                let contract = contract.validate(resolver, true).unwrap_or_else(|c| c);
+                let status = contract.validation_status().expect("just validated");
+                json!({
+                    "contract_id": contract_id.to_string(),
+                    "validity": status.validity().to_string(),
+                    "status": status.to_string(),
+                })
+            }
+            Err(err) => json!({
+                "contract_id": contract_id.to_string(),
+                "validity": Validity::Invalid.to_string(),
+                "status": format!("unable to collect contract history: {err}"),
+            }),
+        };
+        contracts.push(status);
+    }
+    for contract_id in history.keys() {
+        if !contract_ids.contains(contract_id) {
+            index_issues.push(format!("state history of unknown contract {contract_id}"));
+        }
+    }
+
+    for opid in runtime.extension_ids()? {
+        let extension = runtime.extension(opid)?;
+        known_ops.insert(opid);
+        for (_, assigns) in extension.assignments.iter() {
+            seals.extend(assigns.to_confidential_seals());
+        }
+    }
+
+    let bundle_ids = runtime.bundle_ids()?;
+    let mut anchored = BTreeSet::new();
+    for witness_id in runtime.witness_ids()? {
+        let anchor = runtime.anchor(witness_id)?;
+        for (bundle_id, contract_id) in anchor.known_bundle_ids() {
+            anchored.insert(bundle_id);
+            if !bundle_ids.contains(&bundle_id) {
+                index_issues.push(format!(
+                    "anchor {witness_id} commits to bundle {bundle_id} of contract {contract_id} \
+                     which is not in the stash"
+                ));
+            }
+            if runtime.debug_anchor_bundle_index().get(&bundle_id) != Some(&witness_id) {
+                index_issues.push(format!(
+                    "bundle {bundle_id} is not indexed under its anchor {witness_id}"
+                ));
+            }
+        }
+    }
+    for bundle_id in &bundle_ids {
+        if !anchored.contains(bundle_id) {
+            index_issues.push(format!("bundle {bundle_id} has no anchor"));
+        }
+        let bundle = runtime.bundle(*bundle_id)?;
+        for (opid, transition) in &bundle.known_transitions {
+            known_ops.insert(*opid);
+            for (_, assigns) in transition.assignments.iter() {
+                seals.extend(assigns.to_confidential_seals());
+            }
+            inputs.extend(
+                transition
+                    .inputs
+                    .iter()
+                    .map(|input| (*opid, input.prev_out)),
+            );
+            match runtime.anchored_bundle(*opid) {
+                Ok(anchored) if anchored.bundle.bundle_id() == *bundle_id => {}
+                Ok(anchored) => index_issues.push(format!(
+                    "transition {opid} is indexed under bundle {} instead of {bundle_id}",
+                    anchored.bundle.bundle_id()
+                )),
+                Err(err) => index_issues.push(format!("transition {opid}: {err}")),
+            }
+        }
+    }
+    for opid in runtime.debug_bundle_op_index().keys() {
+        if runtime.transition(*opid).is_err() {
+            index_issues.push(format!("index refers to unknown transition {opid}"));
+        }
+    }
+    for (secret, opout) in runtime.debug_terminal_index() {
+        if !known_ops.contains(&opout.op) {
+            index_issues.push(format!(
+                "terminal {secret} refers to unknown operation output {opout}"
+            ));
+        }
+    }
+
+    let dangling_inputs = inputs
+        .into_iter()
+        .filter(|(_, prev_out)| !known_ops.contains(&prev_out.op))
+        .map(|(opid, prev_out)| {
+            json!({
+                "opid": opid.to_string(),
+                "prev_out": prev_out.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let unused_seal_secrets = runtime
+        .seal_secrets()
+        .map_err(RuntimeError::from)?
+        .into_iter()
+        .filter(|seal| !seals.contains(&seal.to_secret_seal()))
+        .count();
+
+    let ok = index_issues.is_empty()
+        && dangling_inputs.is_empty()
+        && contracts
+            .iter()
+            .all(|contract| contract["validity"] == Validity::Valid.to_string());
+    Ok(json!({
+        "ok": ok,
+        "contracts": contracts,
+        "index_issues": index_issues,
+        "dangling_inputs": dangling_inputs,
+        "unused_seal_secrets": unused_seal_secrets,
+    }))
+}
+
+/// Rebuilds the stock inventory from the stash data of `stock`.
+///
+/// The contract state and all the indexes are derived again from the
+/// schemata, interfaces, geneses, extensions, anchors and bundles kept in the
+/// stash, and the seal secrets are carried over. Each contract is validated
+/// with its whole history and the witness transactions are resolved with
+/// `resolver` to get their heights back; histories with unmined or unresolved
+/// witnesses are still indexed and reported with their validity. Bundles
+/// without an anchor cannot be indexed and are dropped only when `force` is
+/// set.
+pub fn reindex_stock<R>(
+    stock: &Stock,
+    resolver: &mut R,
+    force: bool,
+) -> Result<(Stock, JsonValue), anyhow::Error>
+where
+    R: ResolveHeight + ResolveWitness,
+    R::Error: 'static,
+{
+    let mut reindexed = Stock::default();
+
+    for seal in stock.seal_secrets().map_err(RuntimeError::from)? {
+        reindexed
+            .store_seal_secret(seal)
+            .map_err(RuntimeError::from)?;
+    }
+    for id in stock.ifaces()?.keys() {
+        reindexed
+            .import_iface(stock.iface_by_id(*id)?.clone())
+            .map_err(RuntimeError::from)?;
+    }
+    for schema_id in stock.schema_ids()? {
+        let schema_ifaces = stock.schema(schema_id)?;
+        reindexed
+            .import_schema(schema_ifaces.schema.clone())
+            .map_err(RuntimeError::from)?;
+        for iimpl in schema_ifaces.iimpls.values() {
+            reindexed
+                .import_iface_impl(iimpl.clone())
+                .map_err(RuntimeError::from)?;
+        }
+    }
+
+    let mut contracts = vec![];
+    for contract_id in stock.contract_ids()? {
+        let contract = match stash_consignment(stock, contract_id)?.validate(resolver, true) {
+            Ok(contract) => contract,
+            Err(contract)
+                if contract
+                    .validation_status()
+                    .is_some_and(|status| status.validity() != Validity::Invalid) =>
+            {
+                contract
+            }
+            Err(contract) => return Err(RuntimeError::validation(contract).into()),
+        };
+        let validity = contract
+            .validation_status()
+            .map(|status| status.validity().to_string());
+        unsafe { reindexed.import_contract_force(contract, resolver) }
+            .map_err(RuntimeError::from)?;
+        contracts.push(json!({
+            "contract_id": contract_id.to_string(),
+            "validity": validity,
+        }));
+    }
+
+    // The contract histories carry only the merkle proofs of their own
+    // bundles, so the full anchors are put back as well
+    let mut anchored = BTreeSet::new();
+    for witness_id in stock.witness_ids()? {
+        let anchor = stock.anchor(witness_id)?;
+        unsafe { reindexed.consume_anchor(anchor.clone()) }.map_err(RuntimeError::from)?;
+        anchored.extend(
+            anchor
+                .known_bundle_ids()
+                .map(|(bundle_id, _)| bundle_id)
+                .filter(|bundle_id| stock.bundle(*bundle_id).is_ok()),
+        );
+    }
+    let orphaned = stock
+        .bundle_ids()?
+        .difference(&anchored)
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    if !orphaned.is_empty() && !force {
+        return Err(RuntimeError::usage(format!(
+            "bundles {} have no anchor and would be dropped, use --force to proceed",
+            orphaned.join(", ")
+        ))
+        .into());
+    }
+
+    let report = json!({
+        "contracts": contracts,
+        "bundles": anchored.len(),
+        "anchors": reindexed.witness_ids()?.len(),
+        "seal_secrets": reindexed.seal_secrets().map_err(RuntimeError::from)?.len(),
+        "dropped_bundles": orphaned,
+    });
+    Ok((reindexed, report))
+}
+
+/// Builds a consignment with the genesis, extensions and interfaces the stash
+/// keeps for a contract.
+fn genesis_consignment(stock: &Stock, contract_id: ContractId) -> Result<Contract, anyhow::Error> {
+    let genesis = stock.genesis(contract_id)?;
+    let schema_ifaces = stock.schema(genesis.schema_id)?;
+    let mut consignment = Contract::new(
+        schema_ifaces.schema.clone(),
+        genesis.clone(),
+        stock.contract_asset_tags(contract_id)?.clone(),
+    );
+    for (iface_id, iimpl) in &schema_ifaces.iimpls {
+        let iface = stock.iface_by_id(*iface_id)?;
+        consignment
+            .ifaces
+            .insert(*iface_id, IfacePair::with(iface.clone(), iimpl.clone()))?;
+    }
+    if let Some(suppl) = stock.contract_suppl_all(contract_id) {
+        consignment.supplements = suppl.clone();
+    }
+    for opid in stock.extension_ids()? {
+        let extension = stock.extension(opid)?;
+        if extension.contract_id == contract_id {
+            consignment.extensions.push(extension.clone())?;
+        }
+    }
+    Ok(consignment)
+}
+
+/// Builds a consignment with all the history the stash keeps for a contract.
+///
+/// Only the stash data is used, so the result does not depend on the
+/// inventory indexes being consistent.
+fn stash_consignment(stock: &Stock, contract_id: ContractId) -> Result<Contract, anyhow::Error> {
+    let mut consignment = genesis_consignment(stock, contract_id)?;
+    for witness_id in stock.witness_ids()? {
+        let anchor = stock.anchor(witness_id)?;
+        for (bundle_id, id) in anchor.known_bundle_ids() {
+            if id != contract_id {
+                continue;
+            }
+            let bundle = stock.bundle(bundle_id)?.clone();
+            let anchor = anchor
+                .to_merkle_proof(contract_id)
+                .map_err(|err| RuntimeError::Custom(err.to_string()))?;
+            consignment
+                .bundles
+                .push(AnchoredBundle { anchor, bundle })?;
+        }
+    }
+    Ok(consignment)
+}
+
+/// Resolver which knows no witness transactions, so all of them are taken as
+/// unmined.
+pub struct OfflineResolver;
+
+impl ResolveWitness for OfflineResolver {
+    fn resolve_pub_witness(
+        &self,
+        witness_id: WitnessId,
+    ) -> Result<XPubWitness, WitnessResolverError> {
+        Err(WitnessResolverError::Unknown(witness_id))
+    }
+}
+
+impl ResolveHeight for OfflineResolver {
+    type Error = Infallible;
+
+    fn resolve_anchor(&mut self, anchor: &XAnchor) -> Result<WitnessAnchor, Self::Error> {
+        Ok(WitnessAnchor::from_mempool(anchor.witness_id_unchecked()))
+    }
+}
@@ -0,0 +1,637 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use base64::{decode, encode};
+use bdk::bitcoin::hashes::Hash;
+use bdk::bitcoin::psbt::PsbtSighashType;
+use bdk::bitcoin::script::PushBytesBuf;
+use bdk::bitcoin::{Network, OutPoint, Script, ScriptBuf, Transaction};
+use bdk::wallet::tx_builder::TxOrdering;
+use bdk::wallet::AddressIndex;
+use bdk::{
+    blockchain::Blockchain, database::BatchDatabase, Error as BdkError, FeeRate, KeychainKind,
+};
+use psbt::PsbtVer;
+use rgb::AnchorSet;
+use rgbstd::persistence::Stash;
+use rgbstd::{ContractId, XChain};
+use serde_json::json;
+
+use crate::cmds::runtime::Runtime;
+use crate::cmds::runtime::RuntimeError;
+use crate::fee::{fee_report, InputType, InputWeight, TxWeight};
+use crate::opts::{OfflineWalletSubCommand, OnlineWalletSubCommand, WalletOpts};
+use crate::service::{
+    CreateTxRequest, CreateTxResponse, FinalizeRequest, SignRequest, SignResponse, WalletService,
+};
+use crate::utils::{
+    check_sighash, is_final, psbt_input_txout, read_psbt, to_rgb_outpoint, write_psbt,
+};
+
+pub fn handle_online_wallet_subcommand<B, D>(
+    service: &WalletService<D>,
+    blockchain: &B,
+    online_subcommand: OnlineWalletSubCommand,
+) -> Result<serde_json::Value, anyhow::Error>
+where
+    B: Blockchain,
+    D: BatchDatabase,
+{
+    match online_subcommand {
+        OnlineWalletSubCommand::Sync => {
+            service.sync(blockchain)?;
+            Ok(json!("done"))
+        }
+        OnlineWalletSubCommand::Broadcast { psbt_file } => {
+            let (psbt, _) = read_psbt(psbt_file)?;
+            Ok(serde_json::to_value(service.broadcast(blockchain, psbt)?)?)
+        }
+    }
+}
+
+pub fn handle_offline_wallet_subcommand<D>(
+    service: &WalletService<D>,
+    wallet_opts: &WalletOpts,
+    psbt_version: Option<PsbtVer>,
+    offline_subcommand: OfflineWalletSubCommand,
+) -> Result<serde_json::Value, anyhow::Error>
+where
+    D: BatchDatabase,
+{
+    let wallet = service.wallet();
+    match offline_subcommand {
+        OfflineWalletSubCommand::GetNewAddress => {
+            let addr = service.new_address()?;
+            if wallet_opts.verbose {
+                Ok(json!({"address": addr.address, "index": addr.index}))
+            } else {
+                Ok(json!({"address": addr.address}))
+            }
+        }
+        OfflineWalletSubCommand::ListUnspent => Ok(serde_json::to_value(service.list_unspent()?)?),
+        OfflineWalletSubCommand::CreateTx {
+            recipients,
+            send_all,
+            enable_rbf,
+            offline_signer,
+            utxos,
+            unspendable,
+            fee_rate,
+            external_policy,
+            internal_policy,
+            add_data,
+            add_string,
+            psbt_file,
+        } => {
+            let op_return = match (add_data, add_string) {
+                (Some(base64_data), _) => Some(decode(&base64_data).map_err(|err| {
+                    RuntimeError::usage(format!("invalid base64 OP_RETURN data: {err}"))
+                })?),
+                (None, Some(string_data)) => Some(string_data.into_bytes()),
+                (None, None) => None,
+            };
+            let policy = |policy: Option<String>| {
+                policy
+                    .map(|policy| serde_json::from_str::<BTreeMap<String, Vec<usize>>>(&policy))
+                    .transpose()
+            };
+            let request = CreateTxRequest {
+                recipients,
+                send_all,
+                enable_rbf,
+                offline_signer,
+                utxos: utxos.unwrap_or_default(),
+                unspendable: unspendable.unwrap_or_default(),
+                fee_rate,
+                external_policy: policy(external_policy)?,
+                internal_policy: policy(internal_policy)?,
+                op_return,
+            };
+
+            let CreateTxResponse { psbt, details } = service.create_tx(request)?;
+            write_psbt(psbt_file, &psbt, psbt_version.unwrap_or(PsbtVer::V0))?;
+            if wallet_opts.verbose {
+                Ok(json!({"details": details, "psbt": psbt}))
+            } else {
+                Ok(json!({"details": details}))
+            }
+        }
+        OfflineWalletSubCommand::Sign {
+            psbt_file,
+            assume_height,
+            trust_witness_utxo,
+            allow_sighash,
+        } => {
+            let (psbt, version) = read_psbt(&psbt_file)?;
+            let allowed_sighashes = allow_sighash
+                .into_iter()
+                .map(PsbtSighashType::from)
+                .collect::<Vec<_>>();
+
+            // if psbt.inputs.len() != 2 {
+            //     return Err(anyhow!(
+            //         "invalid inputs length: {}, expected: 2",
+            //         psbt.inputs.len()
+            //     ));
+            // }
+            // if psbt.outputs.len() != 3 {
+            //     return Err(anyhow!(
+            //         "invalid outputs length: {}, expected: 3",
+            //         psbt.outputs.len()
+            //     ));
+            // }
+
+            let SignResponse {
+                psbt,
+                is_finalized: finalized,
+            } = service.sign(SignRequest {
+                psbt,
+                assume_height,
+                trust_witness_utxo: trust_witness_utxo.unwrap_or(false),
+                allowed_sighashes,
+            })?;
+            write_psbt(psbt_file, &psbt, psbt_version.unwrap_or(version))?;
+            if wallet_opts.verbose {
+                Ok(json!({"is_finalized": finalized, "psbt": psbt}))
+            } else {
+                Ok(json!({"is_finalized": finalized}))
+            }
+        }
+
+        OfflineWalletSubCommand::Combine {
+            psbt_files,
+            psbt_file,
+        } => {
+            let mut psbts = psbt_files
+                .iter()
+                .map(read_psbt)
+                .collect::<Result<Vec<_>, _>>()?;
+            let (mut psbt, version) = psbts.remove(0);
+            for (other, _) in psbts {
+                psbt.combine(other)?;
+            }
+            write_psbt(&psbt_file, &psbt, psbt_version.unwrap_or(version))?;
+            if wallet_opts.verbose {
+                Ok(json!({"is_finalized": is_final(&psbt).is_ok(), "psbt": psbt}))
+            } else {
+                Ok(json!({"is_finalized": is_final(&psbt).is_ok()}))
+            }
+        }
+        OfflineWalletSubCommand::Finalize {
+            psbt_file,
+            assume_height,
+        } => {
+            let (psbt, version) = read_psbt(&psbt_file)?;
+            let SignResponse {
+                psbt,
+                is_finalized: finalized,
+            } = service.finalize(FinalizeRequest {
+                psbt,
+                assume_height,
+            })?;
+            write_psbt(&psbt_file, &psbt, psbt_version.unwrap_or(version))?;
+            if wallet_opts.verbose {
+                Ok(json!({"is_finalized": finalized, "psbt": psbt}))
+            } else {
+                Ok(json!({"is_finalized": finalized}))
+            }
+        }
+
+        OfflineWalletSubCommand::BumpFee {
+            txid,
+            fee_rate,
+            keep,
+            rgb_data_dir,
+            no_rgb_check,
+            unspendable,
+            psbt_file,
+        } => {
+            let original = wallet
+                .get_tx(&txid, true)?
+                .and_then(|details| details.transaction)
+                .ok_or(BdkError::TransactionNotFound)?;
+
+            // OP_RETURN outputs may carry an opret commitment even when the
+            // stash doesn't know the transaction
+            let mut protected = original
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(_, txout)| txout.script_pubkey.is_op_return())
+                .map(|(vout, _)| vout as u32)
+                .collect::<BTreeSet<_>>();
+            protected.extend(keep);
+            if !no_rgb_check {
+                let runtime = load_rgb_stash(rgb_data_dir, wallet.network())?;
+                protected.extend(rgb_outputs(&runtime, &original)?);
+            }
+
+            let mut tx_builder = wallet.build_fee_bump(txid)?;
+            tx_builder.ordering(TxOrdering::Untouched);
+            tx_builder.fee_rate(FeeRate::from_sat_per_vb(fee_rate));
+            if let Some(unspendable) = unspendable {
+                tx_builder.unspendable(unspendable);
+            }
+            let (psbt, details) = tx_builder.finish()?;
+
+            // The outputs may be reordered, so they are matched by script and value
+            let mut outputs = psbt.unsigned_tx.output.clone();
+            for vout in &protected {
+                let txout = original.output.get(*vout as usize).ok_or_else(|| {
+                    RuntimeError::usage(format!("transaction {} has no output {}", txid, vout))
+                })?;
+                match outputs.iter().position(|output| output == txout) {
+                    Some(pos) => {
+                        outputs.remove(pos);
+                    }
+                    None => {
+                        return Err(RuntimeError::Wallet(bdk::Error::Generic(format!(
+                            "fee bump would change output {} which carries an RGB commitment or \
+                             allocation, refusing to bump",
+                            vout
+                        )))
+                        .into())
+                    }
+                }
+            }
+
+            write_psbt(&psbt_file, &psbt, psbt_version.unwrap_or(PsbtVer::V0))?;
+            let mut final_weight = TxWeight::new();
+            for index in 0..psbt.inputs.len() {
+                let input_weight = match InputType::from_psbt_input(&psbt, index) {
+                    Some(input_type) => input_type.to_input_weight(),
+                    None => InputWeight::from_wallet(wallet, KeychainKind::External)?,
+                };
+                final_weight.add_input(input_weight);
+            }
+            for txout in &psbt.unsigned_tx.output {
+                final_weight.add_output(txout.script_pubkey.len());
+            }
+            let fee_estimation = fee_report(details.fee.unwrap_or_default(), final_weight.vsize());
+            if wallet_opts.verbose {
+                Ok(json!({
+                    "details": details,
+                    "protected_outputs": protected,
+                    "fee_estimation": fee_estimation,
+                    "psbt": psbt,
+                }))
+            } else {
+                Ok(json!({
+                    "details": details,
+                    "protected_outputs": protected,
+                    "fee_estimation": fee_estimation,
+                }))
+            }
+        }
+
+        OfflineWalletSubCommand::Cpfp {
+            parent,
+            target_fee_rate,
+            parent_fee,
+            to,
+            rgb_data_dir,
+            no_rgb_check,
+            enable_rbf,
+            psbt_file,
+        } => {
+            let parent_details = wallet
+                .get_tx(&parent.txid, true)?
+                .ok_or(BdkError::TransactionNotFound)?;
+            if parent_details.confirmation_time.is_some() {
+                return Err(RuntimeError::Wallet(bdk::Error::Generic(format!(
+                    "transaction {} is already confirmed",
+                    parent.txid
+                )))
+                .into());
+            }
+            let parent_tx = parent_details
+                .transaction
+                .ok_or(BdkError::TransactionNotFound)?;
+            let parent_fee = parent_fee.or(parent_details.fee).ok_or_else(|| {
+                RuntimeError::usage(
+                    "fee of the parent transaction is unknown, please provide --parent_fee",
+                )
+            })?;
+            let parent_utxo = wallet.get_utxo(parent)?.ok_or(BdkError::UnknownUtxo)?;
+
+            // Spending an output with RGB allocations without a state transition burns them
+            if !no_rgb_check {
+                let runtime = load_rgb_stash(rgb_data_dir, wallet.network())?;
+                let contracts = runtime.contracts_by_outpoints([to_rgb_outpoint(parent)])?;
+                if let Some(contract_ids) = contracts.values().next() {
+                    return Err(RuntimeError::Wallet(bdk::Error::Generic(format!(
+                        "output {} holds RGB allocations of {}, refusing to spend it",
+                        parent,
+                        contract_ids
+                            .iter()
+                            .map(ContractId::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )))
+                    .into());
+                }
+            }
+
+            let to = match to {
+                Some(script) => script,
+                None => wallet
+                    .get_internal_address(AddressIndex::New)?
+                    .script_pubkey(),
+            };
+
+            let mut child_weight = TxWeight::new();
+            child_weight
+                .add_input(InputWeight::from_wallet(wallet, parent_utxo.keychain)?)
+                .add_output(to.len());
+
+            // The child pays for the missing fee of the whole package, and at
+            // least the minimum relay fee for itself
+            let target_fee_rate = FeeRate::from_sat_per_vb(target_fee_rate);
+            let parent_vsize = parent_tx.vsize() as u64;
+            let package_vsize = parent_vsize + child_weight.vsize();
+            let package_fee = target_fee_rate.fee_vb(package_vsize as usize);
+            let child_fee = package_fee
+                .saturating_sub(parent_fee)
+                .max(child_weight.fee_for(FeeRate::default_min_relay_fee()));
+
+            let mut tx_builder = wallet.build_tx();
+            tx_builder
+                .add_utxo(parent)?
+                .manually_selected_only()
+                .drain_to(to)
+                .fee_absolute(child_fee);
+            if enable_rbf {
+                tx_builder.enable_rbf();
+            }
+            let (psbt, details) = tx_builder.finish()?;
+
+            let psbt_bin = write_psbt(psbt_file, &psbt, psbt_version.unwrap_or(PsbtVer::V0))?;
+            let fee_estimation = json!({
+                "parent": fee_report(parent_fee, parent_vsize),
+                "child": fee_report(child_fee, child_weight.vsize()),
+                "package": fee_report(parent_fee + child_fee, package_vsize),
+            });
+            if wallet_opts.verbose {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                    "serialized_psbt": psbt,
+                }))
+            } else {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                }))
+            }
+        }
+
+        OfflineWalletSubCommand::InitPayfeePsbt {
+            input,
+            output,
+            fee_rate,
+            sender_inputs,
+            sender_input_count,
+            sighash,
+            psbt_file,
+        } => {
+            let input_utxo = wallet.get_utxo(input)?.ok_or(BdkError::UnknownUtxo)?;
+            let receiver_input = InputWeight::from_wallet(wallet, input_utxo.keychain)?;
+
+            // Empty OP_RETURN hosting the opret commitment, see `rgb sethost` and `rgb commit`
+            let push_bytes = PushBytesBuf::new();
+            // The commitment replaces the output script with a 32 bytes push
+            let opret_script = ScriptBuf::new_op_return(&[0u8; 32]);
+
+            let mut receiver_weight = TxWeight::new();
+            receiver_weight
+                .add_input(receiver_input)
+                .add_output(output.0.len())
+                .add_output(opret_script.len());
+
+            // The sender adds each input together with an output of the same script type
+            let mut final_weight = receiver_weight.clone();
+            if sender_inputs.is_empty() {
+                for _ in 0..sender_input_count.unwrap_or(1) {
+                    final_weight
+                        .add_input(receiver_input)
+                        .add_output(input_utxo.txout.script_pubkey.len());
+                }
+            } else {
+                for input_type in &sender_inputs {
+                    final_weight
+                        .add_input(input_type.to_input_weight())
+                        .add_output(input_type.script_len());
+                }
+            }
+            let fee_rate = fee_rate
+                .map(FeeRate::from_sat_per_vb)
+                .unwrap_or_else(FeeRate::default_min_relay_fee);
+
+            let mut tx_builder = wallet.build_tx();
+            tx_builder.ordering(TxOrdering::Untouched);
+            tx_builder.drain_wallet().drain_to(output.0.clone());
+            tx_builder.fee_absolute(final_weight.fee_for(fee_rate));
+
+            tx_builder.add_utxo(input)?.manually_selected_only();
+
+            if let Some(sighash) = sighash {
+                check_sighash(sighash, &input_utxo.txout.script_pubkey)?;
+                tx_builder.sighash(sighash.into());
+            }
+
+            tx_builder.add_data(&push_bytes);
+            let (psbt, details) = tx_builder.finish()?;
+
+            let fee = details.fee.unwrap_or_default();
+            let fee_estimation = json!({
+                "receiver": fee_report(fee, receiver_weight.vsize()),
+                "final": fee_report(fee, final_weight.vsize()),
+            });
+
+            let psbt_bin = write_psbt(psbt_file, &psbt, psbt_version.unwrap_or(PsbtVer::V0))?;
+            if wallet_opts.verbose {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                    "serialized_psbt": psbt,
+                }))
+            } else {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                }))
+            }
+        }
+        OfflineWalletSubCommand::AddSenderInput {
+            input,
+            fee_rate,
+            enable_rbf,
+            sighash,
+            psbt_file,
+        } => {
+            let (old_psbt, version) = read_psbt(&psbt_file)?;
+
+            if old_psbt.inputs.len() != 1 || old_psbt.unsigned_tx.input.len() != 1 {
+                return Err(RuntimeError::usage(format!(
+                    "invalid psbt inputs, inputs.len={} != 1",
+                    old_psbt.inputs.len()
+                ))
+                .into());
+            }
+            if old_psbt.outputs.len() != 2 || old_psbt.unsigned_tx.output.len() != 2 {
+                return Err(RuntimeError::usage(format!(
+                    "invalid psbt outputs, outputs.len={} != 2",
+                    old_psbt.outputs.len()
+                ))
+                .into());
+            }
+            // FIXME: add more checks
+
+            let receiver_txout = psbt_input_txout(&old_psbt, 0)
+                .ok_or_else(|| RuntimeError::usage("receiver input is missing the spent output"))?;
+            let receiver_input = InputType::from_psbt_input(&old_psbt, 0)
+                .ok_or_else(|| {
+                    RuntimeError::unsupported(format!(
+                        "unsupported receiver input script {}",
+                        receiver_txout.script_pubkey
+                    ))
+                })?
+                .to_input_weight();
+            let prepaid_fee = receiver_txout.value.saturating_sub(
+                old_psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .map(|txout| txout.value)
+                    .sum(),
+            );
+
+            let input_utxo = wallet.get_utxo(input)?.ok_or(BdkError::UnknownUtxo)?;
+            let sender_input = InputWeight::from_wallet(wallet, input_utxo.keychain)?;
+            let recipients = [
+                old_psbt.unsigned_tx.output[0].clone(),
+                old_psbt.unsigned_tx.output[1].clone(),
+                input_utxo.txout.clone(),
+            ]
+            .into_iter()
+            .map(|txout| (txout.script_pubkey, txout.value))
+            .collect::<Vec<_>>();
+
+            let mut tx_builder = wallet.build_tx();
+            tx_builder.ordering(TxOrdering::Untouched);
+            // The receiver input may be pre-signed, which commits to the version and lock time
+            tx_builder.version(old_psbt.unsigned_tx.version);
+            tx_builder.nlocktime(old_psbt.unsigned_tx.lock_time);
+            tx_builder.add_foreign_utxo(
+                old_psbt.unsigned_tx.input[0].previous_output,
+                old_psbt.inputs[0].clone(),
+                receiver_input.satisfaction,
+            )?;
+            tx_builder.add_utxo(input)?;
+            tx_builder.set_recipients(recipients);
+
+            // Without an explicit fee rate the fee prepaid by the receiver is kept as is
+            if let Some(fee_rate) = fee_rate {
+                tx_builder.fee_rate(FeeRate::from_sat_per_vb(fee_rate));
+            } else {
+                tx_builder.fee_absolute(prepaid_fee);
+            }
+
+            if enable_rbf {
+                tx_builder.enable_rbf();
+            }
+
+            if let Some(sighash) = sighash {
+                check_sighash(sighash, &input_utxo.txout.script_pubkey)?;
+                tx_builder.sighash(sighash.into());
+            }
+
+            let (mut psbt, details) = tx_builder.finish()?;
+            // Keep the sequence the receiver input was (possibly) signed with
+            psbt.unsigned_tx.input[0].sequence = old_psbt.unsigned_tx.input[0].sequence;
+
+            let mut final_weight = TxWeight::new();
+            final_weight.add_input(receiver_input);
+            for _ in 1..psbt.unsigned_tx.input.len() {
+                final_weight.add_input(sender_input);
+            }
+            for txout in &psbt.unsigned_tx.output {
+                final_weight.add_output(txout.script_pubkey.len());
+            }
+            let fee_estimation = json!({
+                "receiver_prepaid": prepaid_fee,
+                "final": fee_report(details.fee.unwrap_or_default(), final_weight.vsize()),
+            });
+
+            let psbt_bin = write_psbt(&psbt_file, &psbt, psbt_version.unwrap_or(version))?;
+            if wallet_opts.verbose {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                    "serialized_psbt": psbt,
+                }))
+            } else {
+                Ok(json!({
+                    "psbt": encode(psbt_bin),
+                    "details": details,
+                    "fee_estimation": fee_estimation,
+                }))
+            }
+        }
+    }
+}
+
+/// Loads the RGB stash checked before changing or spending wallet outputs.
+fn load_rgb_stash(data_dir: PathBuf, network: Network) -> Result<Runtime, RuntimeError> {
+    let data_dir = PathBuf::from(shellexpand::tilde(&data_dir.to_string_lossy()).as_ref());
+    Runtime::load_existing(data_dir, &network).map_err(|err| {
+        RuntimeError::Custom(format!(
+            "{err}; pass --rgb_data_dir with the stash of this wallet, or --no_rgb_check to skip \
+             the RGB checks"
+        ))
+    })
+}
+
+/// Outputs of a transaction which carry its RGB commitment, according to the
+/// anchor kept in the stash, or hold RGB allocations.
+fn rgb_outputs(runtime: &Runtime, tx: &Transaction) -> Result<BTreeSet<u32>, RuntimeError> {
+    let txid = tx.txid();
+    let mut outputs = BTreeSet::new();
+
+    let witness_id = XChain::Bitcoin(bp::Txid::from(txid.to_byte_array()));
+    if let Ok(anchor) = runtime.anchor(witness_id) {
+        let (tapret, opret) = match anchor.as_reduced_unsafe() {
+            AnchorSet::Tapret(_) => (true, false),
+            AnchorSet::Opret(_) => (false, true),
+            AnchorSet::Dual { .. } => (true, true),
+        };
+        // Commitments are placed into the first output of their type
+        let first = |is_host: fn(&Script) -> bool| {
+            tx.output
+                .iter()
+                .position(|txout| is_host(&txout.script_pubkey))
+                .map(|vout| vout as u32)
+        };
+        if tapret {
+            outputs.extend(first(Script::is_v1_p2tr));
+        }
+        if opret {
+            outputs.extend(first(Script::is_op_return));
+        }
+    }
+
+    let outpoints =
+        (0..tx.output.len() as u32).map(|vout| to_rgb_outpoint(OutPoint::new(txid, vout)));
+    outputs.extend(
+        runtime
+            .contracts_by_outpoints(outpoints)?
+            .keys()
+            .map(|outpoint| outpoint.vout.into_u32()),
+    );
+    Ok(outputs)
+}
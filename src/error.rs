@@ -0,0 +1,172 @@
+use std::convert::Infallible;
+use std::fmt::{Debug, Display};
+use std::io;
+
+use amplify::Display;
+use bdk::electrum_client;
+use psbt::{CommitError, EmbedError, RgbPsbtError};
+use rgb::validation::Validity;
+use rgb_rt::ResolverError;
+use rgbstd::containers::{Consignment, LoadError};
+use rgbstd::interface::BuilderError;
+use rgbstd::persistence::StashError;
+use serde_json::{json, Value as JsonValue};
+use strict_types::encoding::{DecodeError, DeserializeError, SerializeError};
+
+use crate::cmds::runtime::RuntimeError;
+
+/// Kinds of the errors reported by the command line tool.
+///
+/// Each kind of error exits the process with its own code, which never
+/// changes between releases:
+///
+/// | Code | Kind                  | Meaning                                              |
+/// |------|-----------------------|------------------------------------------------------|
+/// | 1    | `internal`            | unexpected failure, a bug of the tool                |
+/// | 2    | `usage`               | invalid arguments or input files (also used by clap) |
+/// | 3    | `io`                  | file system error                                    |
+/// | 4    | `network`             | electrum, esplora or proxy server is not reachable   |
+/// | 5    | `invalid_consignment` | contract or transfer doesn't pass the validation     |
+/// | 6    | `stash`               | missing or inconsistent data in the RGB stash        |
+/// | 7    | `wallet`              | wallet or PSBT error                                 |
+/// | 8    | `unsupported`         | the operation is not supported yet                   |
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display)]
+pub enum ErrorKind {
+    #[display("internal")]
+    Internal = 1,
+    #[display("usage")]
+    Usage = 2,
+    #[display("io")]
+    Io = 3,
+    #[display("network")]
+    Network = 4,
+    #[display("invalid_consignment")]
+    InvalidConsignment = 5,
+    #[display("stash")]
+    Stash = 6,
+    #[display("wallet")]
+    Wallet = 7,
+    #[display("unsupported")]
+    Unsupported = 8,
+}
+
+impl RuntimeError {
+    pub fn usage(msg: impl ToString) -> Self {
+        RuntimeError::Usage(msg.to_string())
+    }
+
+    pub fn network(msg: impl ToString) -> Self {
+        RuntimeError::Network(msg.to_string())
+    }
+
+    pub fn unsupported(msg: impl ToString) -> Self {
+        RuntimeError::Unsupported(msg.to_string())
+    }
+
+    /// Reports a consignment which failed the validation.
+    ///
+    /// Witness transactions which can't be resolved usually mean that the
+    /// indexer is not reachable, so these are reported as network errors.
+    pub fn validation<const TYPE: bool>(consignment: Consignment<TYPE>) -> Self {
+        match consignment.into_validation_status() {
+            Some(status) if status.validity() == Validity::UnresolvedTransactions => {
+                RuntimeError::UnresolvedWitnesses(status)
+            }
+            Some(status) => RuntimeError::InvalidConsignment(status),
+            None => RuntimeError::Internal("consignment is not validated".to_owned()),
+        }
+    }
+
+    /// Kind of the error, see the table in [`ErrorKind`].
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RuntimeError::Io(err) => io_error_kind(err),
+            RuntimeError::Load(LoadError::Decode(DecodeError::Io(_))) => ErrorKind::Io,
+            RuntimeError::Yaml(_)
+            | RuntimeError::Builder(_)
+            | RuntimeError::Deserialize(_)
+            | RuntimeError::Load(_)
+            | RuntimeError::Json(_)
+            | RuntimeError::Usage(_) => ErrorKind::Usage,
+            RuntimeError::Serialize(_) | RuntimeError::Internal(_) => ErrorKind::Internal,
+            RuntimeError::Stash(_) | RuntimeError::Custom(_) => ErrorKind::Stash,
+            RuntimeError::Inventory(_) => ErrorKind::Stash,
+            RuntimeError::Wallet(bdk::Error::Electrum(_)) => ErrorKind::Network,
+            RuntimeError::WalletUnknown(_)
+            | RuntimeError::Psbt(_)
+            | RuntimeError::WalletPsbt(_)
+            | RuntimeError::PsbtDecode(_)
+            | RuntimeError::PsbtRgb(_)
+            | RuntimeError::Embed(_)
+            | RuntimeError::Commit(_)
+            | RuntimeError::Wallet(_) => ErrorKind::Wallet,
+            RuntimeError::Electrum(_)
+            | RuntimeError::Resolver(_)
+            | RuntimeError::UnresolvedWitnesses(_)
+            | RuntimeError::Network(_) => ErrorKind::Network,
+            RuntimeError::InvalidConsignment(_)
+            | RuntimeError::InventoryData(_)
+            | RuntimeError::MalformedConsignment(_)
+            | RuntimeError::IncompleteContract => ErrorKind::InvalidConsignment,
+            RuntimeError::Unsupported(_) => ErrorKind::Unsupported,
+        }
+    }
+
+    /// Process exit code for the error, see the table in [`ErrorKind`].
+    pub fn exit_code(&self) -> i32 {
+        self.kind() as i32
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "error": {
+                "kind": self.kind().to_string(),
+                "code": self.exit_code(),
+                "message": self.to_string(),
+            }
+        })
+    }
+
+    /// Finds out the kind of an error returned by a command.
+    ///
+    /// Errors of the libraries we use are converted into their typed
+    /// counterpart; anything else is not expected and reported as internal.
+    pub fn classify(err: anyhow::Error) -> Self {
+        downcast::<RuntimeError>(err)
+            .or_else(downcast::<io::Error>)
+            .or_else(downcast::<LoadError>)
+            .or_else(downcast::<SerializeError>)
+            .or_else(downcast::<DeserializeError>)
+            .or_else(downcast::<StashError<Infallible>>)
+            .or_else(downcast::<BuilderError>)
+            .or_else(downcast::<CommitError>)
+            .or_else(downcast::<EmbedError>)
+            .or_else(downcast::<RgbPsbtError>)
+            .or_else(downcast::<psbt::DecodeError>)
+            .or_else(downcast::<bdk::bitcoin::psbt::Error>)
+            .or_else(downcast::<bdk::Error>)
+            .or_else(downcast::<electrum_client::Error>)
+            .or_else(downcast::<ResolverError>)
+            .or_else(downcast::<serde_json::Error>)
+            .or_else(downcast::<serde_yaml::Error>)
+            .unwrap_or_else(|err| RuntimeError::Internal(format!("{err:#}")))
+    }
+}
+
+fn downcast<E>(err: anyhow::Error) -> Result<RuntimeError, anyhow::Error>
+where
+    E: Into<RuntimeError> + Display + Debug + Send + Sync + 'static,
+{
+    err.downcast::<E>().map(E::into)
+}
+
+fn io_error_kind(err: &io::Error) -> ErrorKind {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::TimedOut => ErrorKind::Network,
+        _ => ErrorKind::Io,
+    }
+}
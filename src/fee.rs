@@ -0,0 +1,197 @@
+use amplify::Display;
+use bdk::bitcoin::psbt::Psbt;
+use bdk::bitcoin::{Script, Weight};
+use bdk::database::BatchDatabase;
+use bdk::{FeeRate, KeychainKind, Wallet};
+use clap::ValueEnum;
+use serde_json::{json, Value as JsonValue};
+
+use crate::utils::psbt_input_txout;
+
+/// Weight of the outpoint, sequence and the empty scriptSig length of an input.
+const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4 + 1) * 4;
+
+/// Weight of the transaction version and lock time.
+const TX_BASE_WEIGHT: usize = (4 + 4) * 4;
+
+/// Weight of the segwit marker and flag.
+const SEGWIT_HEADER_WEIGHT: usize = 2;
+
+/// Script type of an input the counterparty is expected to add to a transaction.
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum InputType {
+    /// Legacy P2PKH input.
+    Pkh,
+    /// Native segwit v0 P2WPKH input.
+    Wpkh,
+    /// P2WPKH nested in P2SH.
+    #[value(name = "sh-wpkh")]
+    #[display("sh-wpkh")]
+    ShWpkh,
+    /// Taproot key path spend.
+    Tr,
+}
+
+impl InputType {
+    /// Detects the input type from the script pubkey of the spent output.
+    ///
+    /// P2SH outputs are only known to be nested P2WPKH from their redeem
+    /// script; other P2SH scripts, like multisig, are not supported.
+    pub fn from_script(script: &Script, redeem_script: Option<&Script>) -> Option<Self> {
+        if script.is_p2pkh() {
+            Some(InputType::Pkh)
+        } else if script.is_v0_p2wpkh() {
+            Some(InputType::Wpkh)
+        } else if script.is_p2sh() {
+            redeem_script
+                .filter(|redeem_script| redeem_script.is_v0_p2wpkh())
+                .map(|_| InputType::ShWpkh)
+        } else if script.is_v1_p2tr() {
+            Some(InputType::Tr)
+        } else {
+            None
+        }
+    }
+
+    /// Detects the type of a PSBT input from the output it spends and its
+    /// redeem script.
+    pub fn from_psbt_input(psbt: &Psbt, index: usize) -> Option<Self> {
+        let txout = psbt_input_txout(psbt, index)?;
+        let redeem_script = psbt.inputs.get(index)?.redeem_script.as_deref();
+        Self::from_script(&txout.script_pubkey, redeem_script)
+    }
+
+    /// Maximum weight of the scriptSig and witness data satisfying the input.
+    ///
+    /// Matches `miniscript::Descriptor::max_weight_to_satisfy`: ECDSA signatures
+    /// are 73 bytes and Schnorr signatures are 66 bytes, including the push
+    /// opcode and the sighash suffix.
+    pub fn satisfaction_weight(self) -> usize {
+        match self {
+            InputType::Pkh => (73 + 34) * 4,
+            InputType::Wpkh => 73 + 34,
+            InputType::ShWpkh => 23 * 4 + 73 + 34,
+            InputType::Tr => 66,
+        }
+    }
+
+    /// Length of the script pubkey locking an output of this type.
+    pub fn script_len(self) -> usize {
+        match self {
+            InputType::Pkh => 25,
+            InputType::Wpkh => 22,
+            InputType::ShWpkh => 23,
+            InputType::Tr => 34,
+        }
+    }
+
+    pub fn is_segwit(self) -> bool {
+        self != InputType::Pkh
+    }
+
+    pub fn to_input_weight(self) -> InputWeight {
+        InputWeight {
+            satisfaction: self.satisfaction_weight(),
+            segwit: self.is_segwit(),
+        }
+    }
+}
+
+/// Weight of the data required to satisfy a single transaction input.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct InputWeight {
+    /// Weight of the scriptSig and witness, see [`InputType::satisfaction_weight`].
+    pub satisfaction: usize,
+    /// Whether the input is spent with a witness.
+    pub segwit: bool,
+}
+
+impl InputWeight {
+    /// Satisfaction weight of the wallet descriptor for the given keychain.
+    ///
+    /// Miniscript measures the satisfaction against an empty scriptSig, so the
+    /// scriptSig length byte counted with the input base weight is not counted
+    /// twice for legacy descriptors.
+    pub fn from_wallet<D: BatchDatabase>(
+        wallet: &Wallet<D>,
+        keychain: KeychainKind,
+    ) -> Result<Self, anyhow::Error> {
+        let descriptor = wallet.get_descriptor_for_keychain(keychain);
+        Ok(InputWeight {
+            satisfaction: descriptor.max_weight_to_satisfy()?,
+            segwit: descriptor.desc_type().segwit_version().is_some(),
+        })
+    }
+}
+
+/// Weight estimation of a fully signed transaction.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TxWeight {
+    inputs: Vec<InputWeight>,
+    outputs: Vec<usize>,
+}
+
+impl TxWeight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_input(&mut self, input: InputWeight) -> &mut Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds an output with the script pubkey of the given length.
+    pub fn add_output(&mut self, script_len: usize) -> &mut Self {
+        self.outputs.push(script_len);
+        self
+    }
+
+    pub fn weight(&self) -> Weight {
+        let segwit = self.inputs.iter().any(|input| input.segwit);
+        let mut weight =
+            TX_BASE_WEIGHT + varint_len(self.inputs.len()) * 4 + varint_len(self.outputs.len()) * 4;
+        if segwit {
+            weight += SEGWIT_HEADER_WEIGHT;
+        }
+        for input in &self.inputs {
+            weight += TXIN_BASE_WEIGHT + input.satisfaction;
+            // Every input of a segwit transaction carries a witness stack length.
+            if segwit {
+                weight += 1;
+            }
+        }
+        for script_len in &self.outputs {
+            weight += (8 + varint_len(*script_len) + script_len) * 4;
+        }
+        Weight::from_wu(weight as u64)
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.weight().to_vbytes_ceil()
+    }
+
+    /// Absolute fee required to pay the given fee rate.
+    pub fn fee_for(&self, fee_rate: FeeRate) -> u64 {
+        fee_rate.fee_wu(self.weight())
+    }
+}
+
+/// Fee and the effective fee rate for a transaction of the given virtual size.
+pub fn fee_report(fee: u64, vsize: u64) -> JsonValue {
+    json!({
+        "fee": fee,
+        "vsize": vsize,
+        "fee_rate": FeeRate::from_vb(fee, vsize as usize).as_sat_per_vb(),
+    })
+}
+
+fn varint_len(n: usize) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
@@ -0,0 +1,116 @@
+//! BiHelix RGB command line tool as a library.
+//!
+//! [`service`] provides the typed API for embedding the wallet and the RGB
+//! stash into other services. [`handle_command`] runs a parsed command line
+//! and returns the result as JSON, which is what the binary prints.
+
+pub mod cmds;
+pub mod error;
+pub mod fee;
+pub mod opts;
+pub mod output;
+pub mod service;
+mod utils;
+
+use std::fs;
+
+use bdk::bitcoin::consensus::encode::serialize_hex;
+use bdk::bitcoin::Network;
+use psbt::PsbtVer;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cmds::runtime::RuntimeError;
+use crate::cmds::{
+    backup::handle_backup_subcommand,
+    batch::handle_batch,
+    key::handle_key_subcommand,
+    psbt::analyze_psbt,
+    rgb::handle_rgb_subcommand,
+    serve::handle_serve,
+    shell::handle_shell,
+    wallet::{handle_offline_wallet_subcommand, handle_online_wallet_subcommand},
+};
+use crate::opts::{Cli, Command, WalletSubCommand};
+use crate::service::WalletService;
+use crate::utils::{is_final, read_psbt};
+
+pub fn handle_command(cli: Cli) -> Result<JsonValue, anyhow::Error> {
+    let network = cli.network;
+    let psbt_version = cli.psbt_version.map(PsbtVer::from);
+    match cli.command {
+        Command::ExtractPsbt { path, raw: true } => {
+            let (psbt, _) = read_psbt(path)?;
+            Ok(serde_json::to_value(psbt)?)
+        }
+        Command::ExtractPsbt { path, raw: false } => analyze_psbt(&fs::read(path)?, network),
+        Command::ExtractTx { psbt_file } => {
+            let (psbt, _) = read_psbt(psbt_file)?;
+            is_final(&psbt)?;
+            let tx = psbt.extract_tx();
+            Ok(json!({
+                "txid": tx.txid(),
+                "raw_tx": serialize_hex(&tx),
+            }))
+        }
+        Command::Key { subcommand } => handle_key_subcommand(network, subcommand),
+        Command::Wallet {
+            datadir,
+            wallet_opts,
+            subcommand: WalletSubCommand::OnlineWalletSubCommand(online_subcommand),
+        } => {
+            let service = WalletService::open(network, &wallet_opts, datadir)?;
+            let blockchain = WalletService::connect(&wallet_opts)?;
+            handle_online_wallet_subcommand(&service, &blockchain, online_subcommand)
+        }
+        Command::Wallet {
+            datadir,
+            wallet_opts,
+            subcommand: WalletSubCommand::OfflineWalletSubCommand(offline_subcommand),
+        } => {
+            log::info!("wallet_opts: {:?}", wallet_opts);
+            let service = WalletService::open(network, &wallet_opts, datadir)?;
+            handle_offline_wallet_subcommand(
+                &service,
+                &wallet_opts,
+                psbt_version,
+                offline_subcommand,
+            )
+        }
+        Command::Rgb {
+            data_dir,
+            electrum,
+            esplora,
+            subcommand,
+        } => {
+            let chain = rgb_chain(network)?;
+            handle_rgb_subcommand(
+                data_dir,
+                &chain,
+                electrum,
+                esplora,
+                psbt_version,
+                subcommand,
+            )
+        }
+        Command::Backup { subcommand } => handle_backup_subcommand(subcommand),
+        Command::Serve { opts } => handle_serve(rgb_chain(network)?, psbt_version, opts),
+        Command::Batch { opts } => {
+            handle_batch(rgb_chain(network)?, psbt_version, cli.output, opts)
+        }
+        Command::Shell { opts } => handle_shell(
+            rgb_chain(network)?,
+            psbt_version,
+            cli.output,
+            cli.json_errors,
+            opts,
+        ),
+    }
+}
+
+/// Networks supported by the RGB stash.
+fn rgb_chain(network: Network) -> Result<Network, RuntimeError> {
+    match network {
+        Network::Regtest | Network::Testnet | Network::Bitcoin | Network::Signet => Ok(network),
+        _ => Err(RuntimeError::unsupported(format!("network {network}"))),
+    }
+}
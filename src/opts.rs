@@ -2,11 +2,19 @@
 
 use std::path::PathBuf;
 
-use bdk::bitcoin::{Network, OutPoint, ScriptBuf};
-use clap::{Args, Parser, Subcommand, ValueHint};
+use amplify::Display;
+use bdk::bitcoin::psbt::PsbtSighashType;
+use bdk::bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use bdk::bitcoin::{Network, OutPoint, ScriptBuf, Txid};
+use clap::{Args, Parser, Subcommand, ValueEnum, ValueHint};
+use psbt::PsbtVer;
 
-use crate::cmds::{key::KeySubCommand, rgb::RgbSubCommand};
-use crate::utils::{parse_outpoint, parse_proxy_auth, parse_recipient};
+use crate::cmds::{
+    backup::BackupSubCommand, batch::BatchOpts, key::KeySubCommand, rgb::RgbSubCommand,
+    serve::ServeOpts, session::SessionOpts,
+};
+use crate::fee::InputType;
+use crate::utils::{parse_outpoint, parse_proxy_auth, parse_recipient, parse_recipient_address};
 
 #[cfg(any(
     target_os = "linux",
@@ -35,6 +43,23 @@ pub struct Cli {
     )]
     pub network: Network,
 
+    /// PSBT version used when writing PSBT files. Defaults to the version of the input PSBT, or v0 for new ones.
+    #[clap(
+        name = "PSBT_VERSION",
+        long = "psbt-version",
+        global = true,
+        value_enum
+    )]
+    pub psbt_version: Option<PsbtVersion>,
+
+    /// Prints errors to stderr as a JSON object with the error kind and the exit code.
+    #[clap(long, global = true)]
+    pub json_errors: bool,
+
+    /// Format used to print the result of the command.
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Json)]
+    pub output: OutputFormat,
+
     /// Command to execute.
     #[clap(subcommand)]
     pub command: Command,
@@ -47,6 +72,17 @@ pub enum Command {
         /// The PSBT file path (binary format)
         #[clap(short, long)]
         path: PathBuf,
+
+        /// Dump the whole PSBT instead of the summary
+        #[clap(long)]
+        raw: bool,
+    },
+
+    /// Extract the finalized transaction from a psbt file
+    ExtractTx {
+        /// The PSBT file path (binary format)
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
     },
 
     /// Execute key commands.
@@ -64,7 +100,9 @@ pub enum Command {
     Wallet {
         /// Sets the wallet data directory.
         /// Default value : "~/.bdk-bitcoin
-        #[clap(name = "DATADIR", short = 'w', long = "walletdir")]
+        ///
+        /// There is no short form: `-w` selects the wallet name.
+        #[clap(name = "DATADIR", long = "walletdir")]
         datadir: Option<PathBuf>,
         #[clap(flatten)]
         wallet_opts: WalletOpts,
@@ -89,9 +127,40 @@ pub enum Command {
         #[clap(short = 's', long)]
         electrum: Option<String>,
 
+        /// Esplora server resolving the witness transactions.
+        #[clap(long)]
+        esplora: Option<String>,
+
         #[clap(subcommand)]
         subcommand: RgbSubCommand,
     },
+
+    /// Encrypted backups of the RGB stash and the wallet databases.
+    Backup {
+        #[clap(subcommand)]
+        subcommand: BackupSubCommand,
+    },
+
+    /// Runs a JSON-RPC server keeping the RGB stash, the wallets and the
+    /// server connections open between the requests.
+    Serve {
+        #[clap(flatten)]
+        opts: ServeOpts,
+    },
+
+    /// Runs the commands listed in a file against a single RGB stash and
+    /// wallet set, printing one result object per command.
+    Batch {
+        #[clap(flatten)]
+        opts: BatchOpts,
+    },
+
+    /// Interactive shell keeping the RGB stash, a wallet and a contract
+    /// selected between the commands.
+    Shell {
+        #[clap(flatten)]
+        opts: SessionOpts,
+    },
 }
 
 /// Wallet operation subcommands.
@@ -187,9 +256,109 @@ pub enum OfflineWalletSubCommand {
         /// Whether the signer should trust the witness_utxo, if the non_witness_utxo hasn’t been provided.
         #[clap(name = "WITNESS", long = "trust_witness_utxo")]
         trust_witness_utxo: Option<bool>,
+        /// Allows signing inputs with the given non-default sighash type.
+        #[clap(
+            name = "SIGHASH",
+            long = "allow_sighash",
+            alias = "allow-sighash",
+            value_enum
+        )]
+        allow_sighash: Vec<SighashType>,
+    },
+
+    /// Combines multiple PSBTs of the same transaction into one.
+    Combine {
+        /// The PSBT files to combine
+        #[clap(name = "PSBT_FILES", required = true, num_args = 2..)]
+        psbt_files: Vec<PathBuf>,
+
+        /// The output PSBT file path
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
+    },
+    /// Finalizes a signed PSBT without signing it.
+    Finalize {
+        /// The PSBT file path
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
+
+        /// Assume the blockchain has reached a specific height. This affects the transaction finalization, if there are timelocks in the descriptor.
+        #[clap(name = "HEIGHT", long = "assume_height")]
+        assume_height: Option<u32>,
+    },
+
+    /// Bumps the fee of an unconfirmed RBF transaction, keeping its RGB commitment and allocations intact.
+    BumpFee {
+        /// TXID of the transaction to update.
+        #[clap(name = "TXID", long = "txid")]
+        txid: Txid,
+
+        /// The new targeted fee rate in sat/vbyte.
+        #[clap(name = "SATS_VBYTE", short = 'f', long = "fee_rate")]
+        fee_rate: f32,
+
+        /// Output which must be kept unchanged in addition to the RGB commitment and allocations.
+        #[clap(name = "VOUT", long = "keep")]
+        keep: Vec<u32>,
+
+        /// RGB data directory used to find the commitment and the outputs holding RGB allocations.
+        #[clap(long = "rgb_data_dir", default_value = RGB_DATA_DIR, value_hint = ValueHint::DirPath)]
+        rgb_data_dir: PathBuf,
+
+        /// Skips the RGB stash, keeping only OP_RETURN outputs and the `--keep` outputs unchanged.
+        #[clap(long = "no_rgb_check", conflicts_with = "rgb_data_dir")]
+        no_rgb_check: bool,
+
+        /// Marks a utxo as unspendable.
+        #[clap(name = "CANT_SPEND_TXID:VOUT", long = "unspendable", value_parser = parse_outpoint)]
+        unspendable: Option<Vec<OutPoint>>,
+
+        /// The output PSBT file path
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
+    },
+
+    /// Accelerates an unconfirmed transaction by spending one of its outputs in a child paying for the package (CPFP).
+    Cpfp {
+        /// Output of the unconfirmed parent transaction spent by the child.
+        #[clap(name = "PARENT_TXID:VOUT", long = "parent", value_parser = parse_outpoint)]
+        parent: OutPoint,
+
+        /// Fee rate in sat/vbyte targeted for the parent and child package.
+        #[clap(name = "SATS_VBYTE", short = 'f', long = "target_fee_rate")]
+        target_fee_rate: f32,
+
+        /// Fee paid by the parent transaction, required when the wallet doesn't know all of its inputs.
+        #[clap(name = "PARENT_FEE", long = "parent_fee")]
+        parent_fee: Option<u64>,
+
+        /// Address receiving the child output. Defaults to a new change address.
+        #[clap(name = "ADDRESS", long = "to", value_parser = parse_recipient_address)]
+        to: Option<ScriptBuf>,
+
+        /// RGB data directory used to check the parent output doesn't hold RGB allocations.
+        #[clap(long = "rgb_data_dir", default_value = RGB_DATA_DIR, value_hint = ValueHint::DirPath)]
+        rgb_data_dir: PathBuf,
+
+        /// Skips the RGB stash, spending the parent output even if it holds RGB allocations.
+        #[clap(long = "no_rgb_check", conflicts_with = "rgb_data_dir")]
+        no_rgb_check: bool,
+
+        /// Enables Replace-By-Fee (BIP125).
+        #[clap(long = "enable_rbf", short = 'r')]
+        enable_rbf: bool,
+
+        /// The output PSBT file path
+        #[clap(long = "psbt")]
+        psbt_file: PathBuf,
     },
 
     /// Receiver init a Partially Signed Bitcoin Transaction (PSBT) for pay the fee
+    ///
+    /// The PSBT has an empty OP_RETURN in output 0, which hosts the opret
+    /// commitment written by `rgb commit`, and pays the recipient in output 1. The
+    /// fee is computed for the final transaction, with the 32 bytes commitment
+    /// in the OP_RETURN output and the inputs and outputs added by the sender.
     InitPayfeePsbt {
         /// Selects which utxo *must* be spent. (Do NOT have RGB20 token)
         #[clap(name = "TXID:VOUT", long = "input", required = true, value_parser = parse_outpoint)]
@@ -199,10 +368,26 @@ pub enum OfflineWalletSubCommand {
         #[clap(name = "ADDRESS:SAT", long = "to", required = true, value_parser = parse_recipient)]
         output: (ScriptBuf, u64),
 
-        /// Fee rate to use in sat/vbyte. (actual_fee_rate = fee_rate * 2)
+        /// Fee rate to use in sat/vbyte for the final transaction, including the sender inputs.
         #[clap(name = "SATS_VBYTE", short = 'f', long = "fee_rate")]
         fee_rate: Option<f32>,
 
+        /// Script type of each input the sender is expected to add (with its corresponding output).
+        #[clap(name = "INPUT_TYPE", long = "sender_input", value_enum)]
+        sender_inputs: Vec<InputType>,
+
+        /// Number of inputs the sender is expected to add, assuming the same descriptor type as this wallet.
+        #[clap(
+            name = "INPUT_COUNT",
+            long = "sender_input_count",
+            conflicts_with = "INPUT_TYPE"
+        )]
+        sender_input_count: Option<usize>,
+
+        /// Sighash type the receiver input is signed with, allowing to pre-sign it before the sender adds inputs.
+        #[clap(name = "SIGHASH", long = "sighash", value_enum)]
+        sighash: Option<SighashType>,
+
         /// The output PSBT file path
         #[clap(long = "psbt")]
         psbt_file: PathBuf,
@@ -221,12 +406,94 @@ pub enum OfflineWalletSubCommand {
         #[clap(long = "enable_rbf", short = 'r')]
         enable_rbf: bool,
 
+        /// Sighash type the sender input is signed with.
+        #[clap(name = "SIGHASH", long = "sighash", value_enum)]
+        sighash: Option<SighashType>,
+
         /// The PSBT file path
         #[clap(long = "psbt")]
         psbt_file: PathBuf,
     },
 }
 
+/// PSBT serialization version.
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum PsbtVersion {
+    /// Version 0 (BIP174).
+    V0,
+    /// Version 2 (BIP370).
+    V2,
+}
+
+impl From<PsbtVersion> for PsbtVer {
+    fn from(version: PsbtVersion) -> Self {
+        match version {
+            PsbtVersion::V0 => PsbtVer::V0,
+            PsbtVersion::V2 => PsbtVer::V2,
+        }
+    }
+}
+
+/// Format of the command results printed to stdout.
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[display(lowercase)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON.
+    #[default]
+    Json,
+    /// YAML document.
+    Yaml,
+    /// Plain text, one value per line.
+    Text,
+}
+
+/// Sighash flags an input can be signed with.
+///
+/// ECDSA and taproot inputs share the same flag values, except `default`
+/// which is only valid for taproot inputs.
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+pub enum SighashType {
+    /// SIGHASH_DEFAULT, taproot only.
+    #[display("SIGHASH_DEFAULT")]
+    Default,
+    #[display("SIGHASH_ALL")]
+    All,
+    #[display("SIGHASH_NONE")]
+    None,
+    #[display("SIGHASH_SINGLE")]
+    Single,
+    #[value(name = "all-anyonecanpay")]
+    #[display("SIGHASH_ALL|SIGHASH_ANYONECANPAY")]
+    AllPlusAnyoneCanPay,
+    #[value(name = "none-anyonecanpay")]
+    #[display("SIGHASH_NONE|SIGHASH_ANYONECANPAY")]
+    NonePlusAnyoneCanPay,
+    #[value(name = "single-anyonecanpay")]
+    #[display("SIGHASH_SINGLE|SIGHASH_ANYONECANPAY")]
+    SinglePlusAnyoneCanPay,
+}
+
+impl SighashType {
+    pub fn is_taproot_only(self) -> bool {
+        self == SighashType::Default
+    }
+}
+
+impl From<SighashType> for PsbtSighashType {
+    fn from(sighash: SighashType) -> Self {
+        match sighash {
+            SighashType::Default => TapSighashType::Default.into(),
+            SighashType::All => EcdsaSighashType::All.into(),
+            SighashType::None => EcdsaSighashType::None.into(),
+            SighashType::Single => EcdsaSighashType::Single.into(),
+            SighashType::AllPlusAnyoneCanPay => EcdsaSighashType::AllPlusAnyoneCanPay.into(),
+            SighashType::NonePlusAnyoneCanPay => EcdsaSighashType::NonePlusAnyoneCanPay.into(),
+            SighashType::SinglePlusAnyoneCanPay => EcdsaSighashType::SinglePlusAnyoneCanPay.into(),
+        }
+    }
+}
+
 /// Config options wallet operations can take.
 #[derive(Debug, Parser, Clone, PartialEq, Eq)]
 pub struct WalletOpts {
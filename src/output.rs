@@ -0,0 +1,75 @@
+use serde_json::Value as JsonValue;
+
+use crate::opts::OutputFormat;
+
+/// Renders the result of a command in the format requested with `--output`.
+pub fn render(value: &JsonValue, format: OutputFormat) -> Result<String, anyhow::Error> {
+    Ok(match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value)?,
+        OutputFormat::Yaml => serde_yaml::to_string(value)?.trim_end().to_owned(),
+        OutputFormat::Text => {
+            let mut out = String::new();
+            render_text(value, 0, &mut out);
+            out.trim_end().to_owned()
+        }
+    })
+}
+
+/// Strings are printed as is, arrays one item per line and objects as
+/// `key: value` lines, with nested values and multi-line strings indented
+/// below their key.
+fn render_text(value: &JsonValue, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        JsonValue::Array(items) => {
+            for item in items {
+                if is_scalar(item) {
+                    out.push_str(&format!("{pad}{}\n", scalar(item)));
+                } else {
+                    render_text(item, indent, out);
+                    if indent == 0 {
+                        out.push('\n');
+                    }
+                }
+            }
+        }
+        JsonValue::Object(fields) => {
+            for (key, field) in fields {
+                match field {
+                    JsonValue::Array(items) if items.is_empty() => {
+                        out.push_str(&format!("{pad}{key}:\n"))
+                    }
+                    JsonValue::Object(items) if items.is_empty() => {
+                        out.push_str(&format!("{pad}{key}:\n"))
+                    }
+                    JsonValue::String(text) if text.contains('\n') => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        for line in text.lines() {
+                            out.push_str(&format!("{pad}  {line}\n"));
+                        }
+                    }
+                    _ if is_scalar(field) => {
+                        out.push_str(&format!("{pad}{key}: {}\n", scalar(field)))
+                    }
+                    _ => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        render_text(field, indent + 1, out);
+                    }
+                }
+            }
+        }
+        _ => out.push_str(&format!("{pad}{}\n", scalar(value))),
+    }
+}
+
+fn is_scalar(value: &JsonValue) -> bool {
+    !matches!(value, JsonValue::Array(_) | JsonValue::Object(_))
+}
+
+fn scalar(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => "~".to_owned(),
+        other => other.to_string(),
+    }
+}
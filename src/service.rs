@@ -0,0 +1,809 @@
+//! Typed API over the wallet and RGB commands.
+//!
+//! [`WalletService`] and [`RgbService`] take request structs and return
+//! response structs, so the tool can be embedded into other Rust services
+//! instead of being run as a process. The command line front end is built
+//! on top of them and renders the responses as JSON.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use bdk::bitcoin::psbt::{Psbt, PsbtSighashType};
+use bdk::bitcoin::script::PushBytesBuf;
+use bdk::bitcoin::{Address, Network, OutPoint, ScriptBuf, Txid};
+use bdk::blockchain::{log_progress, AnyBlockchain, Blockchain};
+use bdk::database::{AnyDatabase, BatchDatabase};
+use bdk::wallet::AddressIndex;
+use bdk::{FeeRate, KeychainKind, LocalUtxo, SignOptions, SyncOptions, TransactionDetails, Wallet};
+use rgb::validation::{Status, Validity};
+use rgb::BlindingFactor;
+use rgb_rt::Resolver;
+use rgbstd::containers::{Bindle, Contract, Transfer, UniversalBindle};
+use rgbstd::contract::{ContractId, GraphSeal};
+use rgbstd::interface::{rgb20, FilterExclude, IfaceId, ImplId, Rgb20};
+use rgbstd::invoice::{Beneficiary, RgbInvoiceBuilder, XChainNet};
+use rgbstd::persistence::{Inventory, Stash};
+use rgbstd::schema::SchemaId;
+use rgbstd::XChain;
+use seals::txout::SealTxid;
+use serde_crate::{Deserialize, Serialize};
+use strict_types::encoding::TypeName;
+
+use amplify::hex::ToHex;
+
+use crate::cmds::proxy::parse_proxy;
+use crate::cmds::runtime::Runtime;
+use crate::cmds::runtime::RuntimeError;
+use crate::opts::WalletOpts;
+use crate::utils::{
+    check_allowed_sighashes, is_final, maybe_descriptor_wallet_name, new_blockchain, new_wallet,
+    open_database, prepare_home_dir, to_rgb_outpoint,
+};
+
+/// Bitcoin wallet backed by a BDK database.
+pub struct WalletService<D: BatchDatabase> {
+    wallet: Wallet<D>,
+}
+
+/// New address of the wallet.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct AddressInfo {
+    pub address: Address,
+    pub index: u32,
+}
+
+/// Transaction to be funded by the wallet.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", default)]
+pub struct CreateTxRequest {
+    /// Output scripts with the amounts in sats sent to them.
+    pub recipients: Vec<(ScriptBuf, u64)>,
+    /// Sends all the funds of the wallet to the first recipient.
+    pub send_all: bool,
+    pub enable_rbf: bool,
+    /// Adds the redeem and witness scripts of the outputs for offline signers.
+    pub offline_signer: bool,
+    /// Spends only these outputs, when not empty.
+    pub utxos: Vec<OutPoint>,
+    /// Outputs which must not be spent.
+    pub unspendable: Vec<OutPoint>,
+    /// Fee rate in sat/vbyte.
+    pub fee_rate: Option<f32>,
+    pub external_policy: Option<BTreeMap<String, Vec<usize>>>,
+    pub internal_policy: Option<BTreeMap<String, Vec<usize>>>,
+    /// Data of an OP_RETURN output.
+    pub op_return: Option<Vec<u8>>,
+}
+
+/// Unsigned transaction created by the wallet.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct CreateTxResponse {
+    pub psbt: Psbt,
+    pub details: TransactionDetails,
+}
+
+/// PSBT to be signed by the wallet.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct SignRequest {
+    pub psbt: Psbt,
+    #[serde(default)]
+    pub assume_height: Option<u32>,
+    #[serde(default)]
+    pub trust_witness_utxo: bool,
+    /// Sighash flags the inputs may be signed with besides the default ones.
+    #[serde(default)]
+    pub allowed_sighashes: Vec<PsbtSighashType>,
+}
+
+/// PSBT to be finalized by the wallet.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct FinalizeRequest {
+    pub psbt: Psbt,
+    #[serde(default)]
+    pub assume_height: Option<u32>,
+}
+
+/// PSBT after signing or finalization.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct SignResponse {
+    pub psbt: Psbt,
+    pub is_finalized: bool,
+}
+
+/// Transaction sent to the network.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct BroadcastResponse {
+    pub txid: Txid,
+    #[serde(rename = "tx.weight")]
+    pub weight: u64,
+    #[serde(rename = "tx.size")]
+    pub size: usize,
+    #[serde(rename = "tx.vsize")]
+    pub vsize: usize,
+}
+
+impl WalletService<AnyDatabase> {
+    /// Opens the wallet database in the `datadir`, `~/.bdk-bitcoin` by default.
+    pub fn open(
+        network: Network,
+        wallet_opts: &WalletOpts,
+        datadir: Option<PathBuf>,
+    ) -> Result<Self, anyhow::Error> {
+        let home_dir = prepare_home_dir(datadir)?;
+        let wallet_opts = maybe_descriptor_wallet_name(wallet_opts.clone(), network)?;
+        let database = open_database(&wallet_opts, &home_dir)?;
+        Ok(WalletService::new(new_wallet(
+            network,
+            &wallet_opts,
+            database,
+        )?))
+    }
+
+    /// Connects to the electrum server of the wallet options.
+    pub fn connect(wallet_opts: &WalletOpts) -> Result<AnyBlockchain, anyhow::Error> {
+        new_blockchain(wallet_opts)
+    }
+}
+
+impl<D: BatchDatabase> WalletService<D> {
+    pub fn new(wallet: Wallet<D>) -> Self {
+        WalletService { wallet }
+    }
+
+    pub fn wallet(&self) -> &Wallet<D> {
+        &self.wallet
+    }
+
+    pub fn into_wallet(self) -> Wallet<D> {
+        self.wallet
+    }
+
+    pub fn sync<B: Blockchain>(&self, blockchain: &B) -> Result<(), anyhow::Error> {
+        self.wallet.sync(
+            blockchain,
+            SyncOptions {
+                progress: Some(Box::new(log_progress())),
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn new_address(&self) -> Result<AddressInfo, anyhow::Error> {
+        let info = self.wallet.get_address(AddressIndex::New)?;
+        Ok(AddressInfo {
+            address: info.address,
+            index: info.index,
+        })
+    }
+
+    pub fn list_unspent(&self) -> Result<Vec<LocalUtxo>, anyhow::Error> {
+        Ok(self.wallet.list_unspent()?)
+    }
+
+    pub fn create_tx(&self, request: CreateTxRequest) -> Result<CreateTxResponse, anyhow::Error> {
+        let mut tx_builder = self.wallet.build_tx();
+
+        if request.send_all {
+            let (script, _) = request
+                .recipients
+                .first()
+                .ok_or_else(|| RuntimeError::usage("no recipient to send all the funds to"))?;
+            tx_builder.drain_wallet().drain_to(script.clone());
+        } else {
+            tx_builder.set_recipients(request.recipients);
+        }
+
+        if request.enable_rbf {
+            tx_builder.enable_rbf();
+        }
+        if request.offline_signer {
+            tx_builder.include_output_redeem_witness_script();
+        }
+        if let Some(fee_rate) = request.fee_rate {
+            tx_builder.fee_rate(FeeRate::from_sat_per_vb(fee_rate));
+        }
+        if !request.utxos.is_empty() {
+            tx_builder
+                .add_utxos(&request.utxos)?
+                .manually_selected_only();
+        }
+        if !request.unspendable.is_empty() {
+            tx_builder.unspendable(request.unspendable);
+        }
+        if let Some(data) = request.op_return {
+            let push_bytes = PushBytesBuf::try_from(data)
+                .map_err(|_| RuntimeError::usage("OP_RETURN data is too long"))?;
+            tx_builder.add_data(&push_bytes);
+        }
+
+        let policies = [
+            request.external_policy.map(|p| (p, KeychainKind::External)),
+            request.internal_policy.map(|p| (p, KeychainKind::Internal)),
+        ];
+        for (policy, keychain) in policies.into_iter().flatten() {
+            tx_builder.policy_path(policy, keychain);
+        }
+
+        let (psbt, details) = tx_builder.finish()?;
+        Ok(CreateTxResponse { psbt, details })
+    }
+
+    pub fn sign(&self, request: SignRequest) -> Result<SignResponse, anyhow::Error> {
+        let SignRequest {
+            mut psbt,
+            assume_height,
+            trust_witness_utxo,
+            allowed_sighashes,
+        } = request;
+        check_allowed_sighashes(&psbt, &allowed_sighashes)?;
+        let options = SignOptions {
+            assume_height,
+            trust_witness_utxo,
+            allow_all_sighashes: !allowed_sighashes.is_empty(),
+            ..Default::default()
+        };
+        let is_finalized = self.wallet.sign(&mut psbt, options)?;
+        Ok(SignResponse { psbt, is_finalized })
+    }
+
+    pub fn finalize(&self, request: FinalizeRequest) -> Result<SignResponse, anyhow::Error> {
+        let FinalizeRequest {
+            mut psbt,
+            assume_height,
+        } = request;
+        let options = SignOptions {
+            assume_height,
+            ..Default::default()
+        };
+        let is_finalized = self.wallet.finalize_psbt(&mut psbt, options)?;
+        Ok(SignResponse { psbt, is_finalized })
+    }
+
+    /// Extracts the transaction from a finalized PSBT and broadcasts it.
+    pub fn broadcast<B: Blockchain>(
+        &self,
+        blockchain: &B,
+        psbt: Psbt,
+    ) -> Result<BroadcastResponse, anyhow::Error> {
+        is_final(&psbt)?;
+        let tx = psbt.extract_tx();
+        blockchain.broadcast(&tx)?;
+        Ok(BroadcastResponse {
+            txid: tx.txid(),
+            weight: tx.weight().to_wu(),
+            size: tx.size(),
+            vsize: tx.vsize(),
+        })
+    }
+}
+
+/// RGB stash together with the resolver of the witness transactions.
+pub struct RgbService {
+    pub(crate) runtime: Runtime,
+    pub(crate) resolver: Resolver,
+    pub(crate) network: Network,
+}
+
+/// Known RGB schema with the interfaces it implements.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct SchemaInfo {
+    #[serde(with = "display_from_str")]
+    pub schema_id: SchemaId,
+    pub interfaces: Vec<String>,
+}
+
+/// Known RGB interface.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct IfaceInfo {
+    pub name: String,
+    #[serde(with = "display_from_str")]
+    pub iface_id: IfaceId,
+}
+
+/// Contract from the stash, with the RGB20 details when the contract
+/// implements this interface.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct ContractSummary {
+    #[serde(with = "display_from_str")]
+    pub contract_id: ContractId,
+    #[serde(with = "display_from_str")]
+    pub schema_id: SchemaId,
+    pub interfaces: Vec<String>,
+    #[serde(flatten)]
+    pub rgb20: Option<Rgb20Summary>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Rgb20Summary {
+    pub ticker: String,
+    pub name: String,
+    pub details: Option<String>,
+    pub precision: u8,
+    pub issued_supply: u64,
+    /// Creation date in RFC 3339 format.
+    pub created: Option<String>,
+}
+
+/// State of a contract seen through one of its interfaces.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct StateRequest {
+    #[serde(with = "display_from_str")]
+    pub contract_id: ContractId,
+    pub iface: String,
+    /// Includes the allocations which are not owned by the wallet.
+    #[serde(default)]
+    pub all: bool,
+    /// Reports only the allocations on these outputs.
+    #[serde(default)]
+    pub utxos: Option<BTreeSet<OutPoint>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct ContractState {
+    #[serde(with = "display_from_str")]
+    pub contract_id: ContractId,
+    pub interface: String,
+    /// Values of the global state, by the state name.
+    pub global: BTreeMap<String, Vec<String>>,
+    /// Fungible allocations, by the assignment name.
+    pub owned: BTreeMap<String, Vec<Allocation>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Allocation {
+    pub amount: u64,
+    pub seal: String,
+    pub witness: String,
+    pub owner: AllocationOwner,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "lowercase")]
+pub enum AllocationOwner {
+    Wallet,
+    Unknown,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct BlindingResponse {
+    pub blinding_factor: String,
+}
+
+/// Bindle added to the stash.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", tag = "kind", rename_all = "lowercase")]
+pub enum Imported {
+    Interface {
+        #[serde(with = "display_from_str")]
+        id: IfaceId,
+        name: String,
+    },
+    Schema {
+        #[serde(with = "display_from_str")]
+        id: SchemaId,
+    },
+    Implementation {
+        #[serde(with = "display_from_str")]
+        id: ImplId,
+        #[serde(with = "display_from_str")]
+        iface_id: IfaceId,
+        #[serde(with = "display_from_str")]
+        schema_id: SchemaId,
+    },
+    Contract {
+        #[serde(with = "display_from_str")]
+        id: ContractId,
+    },
+}
+
+/// Result of the validation of a consignment.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "snake_case")]
+pub enum ConsignmentValidity {
+    Valid,
+    UnminedTerminals,
+    UnresolvedTransactions,
+    Invalid,
+}
+
+impl From<Validity> for ConsignmentValidity {
+    fn from(validity: Validity) -> Self {
+        match validity {
+            Validity::Valid => ConsignmentValidity::Valid,
+            Validity::UnminedTerminals => ConsignmentValidity::UnminedTerminals,
+            Validity::UnresolvedTransactions => ConsignmentValidity::UnresolvedTransactions,
+            Validity::Invalid => ConsignmentValidity::Invalid,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct ValidationReport {
+    #[serde(with = "display_from_str")]
+    pub contract_id: ContractId,
+    pub validity: ConsignmentValidity,
+    /// Failures and warnings of the validation.
+    pub status: Option<String>,
+}
+
+impl ValidationReport {
+    pub(crate) fn new(contract_id: ContractId, status: Option<&Status>) -> Self {
+        ValidationReport {
+            contract_id,
+            validity: status.map_or(Validity::Valid, Status::validity).into(),
+            status: status.map(Status::to_string),
+        }
+    }
+}
+
+/// Invoice for receiving the state of a contract on a wallet output.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct InvoiceRequest {
+    #[serde(with = "display_from_str")]
+    pub contract_id: ContractId,
+    pub iface: String,
+    pub amount: u64,
+    /// Output receiving the state, which is blinded in the invoice.
+    pub seal: OutPoint,
+    /// RGB proxy the payer posts the consignment to.
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct InvoiceResponse {
+    pub invoice: String,
+}
+
+impl RgbService {
+    /// Loads the stash of the `chain` from the data directory and connects
+    /// to the esplora server used to resolve witness transactions.
+    pub fn load(data_dir: PathBuf, chain: Network, esplora: &str) -> Result<Self, anyhow::Error> {
+        let resolver = Resolver::new(esplora)?;
+        let runtime = Runtime::load(data_dir, &chain)?;
+        Ok(RgbService::new(runtime, resolver, chain))
+    }
+
+    pub fn new(runtime: Runtime, resolver: Resolver, network: Network) -> Self {
+        RgbService {
+            runtime,
+            resolver,
+            network,
+        }
+    }
+
+    /// Network of the stash, which invoices are issued for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    pub fn runtime_mut(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    pub fn schemata(&self) -> Result<Vec<SchemaInfo>, anyhow::Error> {
+        let mut schemata = vec![];
+        for schema_id in self.runtime.schema_ids()? {
+            let mut interfaces = vec![];
+            for iimpl in self.runtime.schema(schema_id)?.iimpls.values() {
+                let iface = self.runtime.iface_by_id(iimpl.iface_id)?;
+                interfaces.push(iface.name.to_string());
+            }
+            schemata.push(SchemaInfo {
+                schema_id,
+                interfaces,
+            });
+        }
+        Ok(schemata)
+    }
+
+    pub fn interfaces(&self) -> Result<Vec<IfaceInfo>, anyhow::Error> {
+        Ok(self
+            .runtime
+            .ifaces()?
+            .into_iter()
+            .map(|(iface_id, name)| IfaceInfo {
+                name: name.to_string(),
+                iface_id,
+            })
+            .collect())
+    }
+
+    pub fn contracts(&self) -> Result<Vec<ContractId>, anyhow::Error> {
+        Ok(self.runtime.contract_ids()?.into_iter().collect())
+    }
+
+    pub fn contract(&self, contract_id: ContractId) -> Result<ContractSummary, anyhow::Error> {
+        let schema_ifaces = self.runtime.contract_schema(contract_id)?;
+        let mut interfaces = vec![];
+        for iface_id in schema_ifaces.iimpls.keys() {
+            let iface = self.runtime.iface_by_id(*iface_id)?;
+            interfaces.push(iface.name.to_string());
+        }
+
+        let rgb20_id = rgb20().iface_id();
+        let rgb20 = if schema_ifaces.iimpls.contains_key(&rgb20_id) {
+            let contract = Rgb20::from(
+                self.runtime
+                    .contract_iface_id(contract_id, rgb20_id)
+                    .map_err(RuntimeError::from)?,
+            );
+            let spec = contract.spec();
+            Some(Rgb20Summary {
+                ticker: spec.ticker().to_owned(),
+                name: spec.name().to_owned(),
+                details: spec.details().map(ToOwned::to_owned),
+                precision: spec.precision.decimals(),
+                issued_supply: contract.total_issued_supply().value(),
+                created: contract
+                    .created()
+                    .to_utc()
+                    .map(|created| created.to_rfc3339()),
+            })
+        } else {
+            None
+        };
+
+        Ok(ContractSummary {
+            contract_id,
+            schema_id: schema_ifaces.schema.schema_id(),
+            interfaces,
+            rgb20,
+        })
+    }
+
+    pub fn state(&self, request: StateRequest) -> Result<ContractState, anyhow::Error> {
+        let iface = TypeName::try_from(request.iface)
+            .map_err(|err| RuntimeError::usage(format!("invalid interface name: {err}")))?;
+        let iface = self.runtime.iface_by_name(&iface)?.clone();
+        let contract = self
+            .runtime
+            .contract_iface_id(request.contract_id, iface.iface_id())
+            .map_err(RuntimeError::from)?;
+
+        let mut global = BTreeMap::new();
+        for spec in &contract.iface.global_state {
+            if let Ok(values) = contract.global(spec.name.clone()) {
+                let values = values.iter().map(|val| val.to_string()).collect();
+                global.insert(spec.name.to_string(), values);
+            }
+        }
+
+        let utxos = request.utxos.map(|utxos| {
+            utxos
+                .into_iter()
+                .map(|utxo| utxo.to_string())
+                .collect::<BTreeSet<_>>()
+        });
+        let mut owned = BTreeMap::new();
+        for spec in &contract.iface.assignments {
+            let mut allocations = vec![];
+            if let Ok(list) = contract.fungible(spec.name.clone(), &self.runtime) {
+                allocations.extend(
+                    list.iter()
+                        .filter(|x| {
+                            let Some(utxos) = &utxos else { return true };
+                            let seal = x.owner.as_reduced_unsafe();
+                            seal.txid
+                                .map_to_outpoint(seal.vout)
+                                .is_some_and(|outpoint| utxos.contains(&outpoint.to_string()))
+                        })
+                        .map(|x| Allocation {
+                            amount: x.value,
+                            seal: x.owner.to_string(),
+                            witness: x.witness.to_string(),
+                            owner: AllocationOwner::Wallet,
+                        }),
+                );
+            }
+            if request.all && utxos.is_none() {
+                if let Ok(list) =
+                    contract.fungible(spec.name.clone(), &FilterExclude(&self.runtime))
+                {
+                    allocations.extend(list.iter().map(|x| Allocation {
+                        amount: x.value,
+                        seal: x.owner.to_string(),
+                        witness: x.witness.to_string(),
+                        owner: AllocationOwner::Unknown,
+                    }));
+                }
+            }
+            // TODO: Report other types of state
+            owned.insert(spec.name.to_string(), allocations);
+        }
+
+        Ok(ContractState {
+            contract_id: request.contract_id,
+            interface: iface.name.to_string(),
+            global,
+            owned,
+        })
+    }
+
+    pub fn blinding(&self) -> BlindingResponse {
+        BlindingResponse {
+            blinding_factor: BlindingFactor::random().to_hex(),
+        }
+    }
+
+    /// Adds an interface, schema, implementation or contract to the stash.
+    pub fn import(&mut self, bindle: UniversalBindle) -> Result<Imported, anyhow::Error> {
+        let imported = match bindle {
+            UniversalBindle::Iface(iface) => {
+                let imported = Imported::Interface {
+                    id: iface.id(),
+                    name: iface.name.to_string(),
+                };
+                self.runtime
+                    .import_iface(iface)
+                    .map_err(RuntimeError::from)?;
+                imported
+            }
+            UniversalBindle::Schema(schema) => {
+                let imported = Imported::Schema { id: schema.id() };
+                self.runtime
+                    .import_schema(schema)
+                    .map_err(RuntimeError::from)?;
+                imported
+            }
+            UniversalBindle::Impl(iimpl) => {
+                let imported = Imported::Implementation {
+                    id: iimpl.id(),
+                    iface_id: iimpl.iface_id,
+                    schema_id: iimpl.schema_id,
+                };
+                self.runtime
+                    .import_iface_impl(iimpl)
+                    .map_err(RuntimeError::from)?;
+                imported
+            }
+            UniversalBindle::Contract(bindle) => {
+                let id = bindle.id();
+                let contract = bindle
+                    .unbindle()
+                    .validate(&mut self.resolver, true)
+                    .map_err(RuntimeError::validation)?;
+                self.runtime.import_contract(contract, &mut self.resolver)?;
+                Imported::Contract { id }
+            }
+            UniversalBindle::Transfer(_) => {
+                return Err(RuntimeError::usage(
+                    "transfers are added to the stash with the accept command",
+                )
+                .into())
+            }
+        };
+        self.runtime.store()?;
+        Ok(imported)
+    }
+
+    pub fn export(&self, contract_id: ContractId) -> Result<Bindle<Contract>, anyhow::Error> {
+        Ok(self
+            .runtime
+            .export_contract(contract_id)
+            .map_err(|err| RuntimeError::Custom(err.to_string()))?)
+    }
+
+    /// Validates a newly issued contract and adds it to the stash.
+    pub fn issue(&mut self, contract: Contract) -> Result<ContractSummary, anyhow::Error> {
+        let id = contract.contract_id();
+        let contract = contract
+            .validate(&mut self.resolver, true)
+            .map_err(RuntimeError::validation)?;
+        self.runtime.import_contract(contract, &mut self.resolver)?;
+        self.runtime.store()?;
+        self.contract(id)
+    }
+
+    /// Validates a transfer without adding it to the stash.
+    pub fn validate(&mut self, transfer: Transfer) -> Result<ValidationReport, anyhow::Error> {
+        self.resolver.add_terminals(&transfer);
+        let transfer = transfer
+            .validate(&mut self.resolver, true)
+            .map_err(RuntimeError::validation)?;
+        Ok(ValidationReport::new(
+            transfer.contract_id(),
+            transfer.validation_status(),
+        ))
+    }
+
+    /// Validates a transfer and adds it to the stash. With `force` a transfer
+    /// which fails the validation is accepted as well.
+    pub fn accept(
+        &mut self,
+        transfer: Transfer,
+        force: bool,
+    ) -> Result<ValidationReport, anyhow::Error> {
+        self.resolver.add_terminals(&transfer);
+        let transfer = match transfer.validate(&mut self.resolver, true) {
+            Ok(transfer) => transfer,
+            Err(transfer) if force => transfer,
+            Err(transfer) => return Err(RuntimeError::validation(transfer).into()),
+        };
+        let report = ValidationReport::new(transfer.contract_id(), transfer.validation_status());
+        self.runtime
+            .accept_transfer(transfer, &mut self.resolver, force)?;
+        self.runtime.store()?;
+        Ok(report)
+    }
+
+    /// Creates an invoice paying to a blinded seal on the given output. The
+    /// seal secret is kept in the stash to recognize the incoming transfer.
+    pub fn invoice(&mut self, request: InvoiceRequest) -> Result<InvoiceResponse, anyhow::Error> {
+        let iface = TypeName::try_from(request.iface)
+            .map_err(|err| RuntimeError::usage(format!("invalid interface name: {err}")))?;
+        let network = match self.network {
+            Network::Bitcoin => bpstd::Network::Mainnet,
+            Network::Testnet => bpstd::Network::Testnet3,
+            Network::Signet => bpstd::Network::Signet,
+            Network::Regtest => bpstd::Network::Regtest,
+            network => return Err(RuntimeError::unsupported(format!("network {network}")).into()),
+        };
+        let output = to_rgb_outpoint(request.seal);
+        let graph_seal = XChain::Bitcoin(GraphSeal::opret_first_rand_from(output));
+        self.runtime
+            .store_seal_secret(graph_seal)
+            .map_err(RuntimeError::from)?;
+        self.runtime.store()?;
+        let beneficiary =
+            Beneficiary::BlindedSeal(*graph_seal.to_secret_seal().as_reduced_unsafe());
+
+        let mut invoice = RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
+            .set_contract(request.contract_id)
+            .set_interface(iface)
+            .set_amount_raw(request.amount)
+            .finish();
+        // The builder keeps its "unspecified" placeholder when a transport is
+        // added, which makes the invoice unparsable.
+        if let Some(proxy) = &request.proxy {
+            invoice.transports = vec![parse_proxy(proxy)?];
+        }
+        Ok(InvoiceResponse {
+            invoice: invoice.to_string(),
+        })
+    }
+}
+
+/// RGB identifiers are (de)serialized in their string form, like on the
+/// command line.
+mod display_from_str {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde_crate::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(val: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(val)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        T::from_str(&s).map_err(de::Error::custom)
+    }
+}
@@ -1,20 +1,28 @@
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::cmds::runtime::RuntimeError;
+use crate::opts::{SighashType, WalletOpts};
 use anyhow::anyhow;
 use bdk::blockchain::{
     AnyBlockchain, AnyBlockchainConfig, ConfigurableBlockchain, ElectrumBlockchainConfig,
 };
 use bdk::{
-    bitcoin::{psbt::Psbt, secp256k1::Secp256k1, Address, Network, OutPoint},
-    database::{any::SledDbConfiguration,
-         AnyDatabase, AnyDatabaseConfig, BatchDatabase,
+    bitcoin::{
+        hashes::Hash,
+        psbt::{Psbt, PsbtSighashType},
+        secp256k1::Secp256k1,
+        sighash::{EcdsaSighashType, TapSighashType},
+        Address, Network, OutPoint, Script, TxOut,
+    },
+    database::{
+        any::SledDbConfiguration, AnyDatabase, AnyDatabaseConfig, BatchDatabase,
         ConfigurableDatabase,
     },
     wallet::wallet_name_from_descriptor,
     Wallet,
 };
-use crate::opts::WalletOpts;
+use psbt::PsbtVer;
 
 /// Create a randomized wallet name from the descriptor checksum.
 /// If wallet options already includes a name, use that instead.
@@ -45,13 +53,13 @@ pub(crate) fn open_database(
     home_path: &Path,
 ) -> Result<AnyDatabase, anyhow::Error> {
     let wallet_name = wallet_opts.wallet.as_ref().expect("wallet name");
-    let database_path = prepare_wallet_db_dir(&wallet_name, home_path)?;
+    let database_path = prepare_wallet_db_dir(wallet_name, home_path)?;
     let config = AnyDatabaseConfig::Sled(SledDbConfiguration {
         path: database_path
             .into_os_string()
             .into_string()
             .expect("path string"),
-        tree_name: wallet_name.clone()
+        tree_name: wallet_name.clone(),
     });
     let database = AnyDatabase::from_config(&config)?;
     log::debug!("database opened successfully");
@@ -99,7 +107,7 @@ pub(crate) fn prepare_home_dir(home_path: Option<PathBuf>) -> Result<PathBuf, an
     let dir = home_path.unwrap_or_else(|| {
         let mut dir = PathBuf::new();
         dir.push(
-            &dirs_next::home_dir()
+            dirs_next::home_dir()
                 .ok_or_else(|| anyhow!("home dir not found"))
                 .unwrap(),
         );
@@ -140,6 +148,12 @@ pub(crate) fn parse_recipient(s: &str) -> Result<(bdk::bitcoin::ScriptBuf, u64),
     Ok((addr.payload.script_pubkey(), val))
 }
 
+/// Parse an address argument from cli input into its script pubkey.
+pub(crate) fn parse_recipient_address(s: &str) -> Result<bdk::bitcoin::ScriptBuf, String> {
+    let addr = Address::from_str(s).map_err(|e| e.to_string())?;
+    Ok(addr.payload.script_pubkey())
+}
+
 /// Parse a outpoint (Txid:Vout) argument from cli input.
 pub(crate) fn parse_outpoint(s: &str) -> Result<OutPoint, String> {
     OutPoint::from_str(s).map_err(|e| e.to_string())
@@ -165,11 +179,20 @@ pub(crate) fn is_final(psbt: &Psbt) -> Result<(), anyhow::Error> {
     let unsigned_tx_inputs = psbt.unsigned_tx.input.len();
     let psbt_inputs = psbt.inputs.len();
     if unsigned_tx_inputs != psbt_inputs {
-        return Err(anyhow!(
+        return Err(RuntimeError::Wallet(bdk::Error::Generic(format!(
             "Malformed PSBT, {} unsigned tx inputs and {} psbt inputs.",
-            unsigned_tx_inputs,
-            psbt_inputs
-        ));
+            unsigned_tx_inputs, psbt_inputs
+        )))
+        .into());
+    }
+    let unsigned_tx_outputs = psbt.unsigned_tx.output.len();
+    let psbt_outputs = psbt.outputs.len();
+    if unsigned_tx_outputs != psbt_outputs {
+        return Err(RuntimeError::Wallet(bdk::Error::Generic(format!(
+            "Malformed PSBT, {} unsigned tx outputs and {} psbt outputs.",
+            unsigned_tx_outputs, psbt_outputs
+        )))
+        .into());
     }
     let sig_count = psbt.inputs.iter().fold(0, |count, input| {
         if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
@@ -179,9 +202,117 @@ pub(crate) fn is_final(psbt: &Psbt) -> Result<(), anyhow::Error> {
         }
     });
     if unsigned_tx_inputs > sig_count {
-        return Err(anyhow!(
-            "The PSBT is not finalized, inputs are are not fully signed."
-        ));
+        return Err(RuntimeError::Wallet(bdk::Error::Generic(
+            "The PSBT is not finalized, inputs are are not fully signed.".to_owned(),
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Get the previous output spent by the PSBT input at the given index.
+pub(crate) fn psbt_input_txout(psbt: &Psbt, index: usize) -> Option<TxOut> {
+    let input = psbt.inputs.get(index)?;
+    if let Some(txout) = &input.witness_utxo {
+        return Some(txout.clone());
+    }
+    let vout = psbt.unsigned_tx.input.get(index)?.previous_output.vout;
+    input
+        .non_witness_utxo
+        .as_ref()?
+        .output
+        .get(vout as usize)
+        .cloned()
+}
+
+/// Check the sighash type can be used to sign an input spending the given script.
+pub(crate) fn check_sighash(sighash: SighashType, script: &Script) -> Result<(), anyhow::Error> {
+    if sighash.is_taproot_only() && !script.is_v1_p2tr() {
+        return Err(RuntimeError::usage(format!(
+            "{} can only be used with taproot inputs, input spends {}",
+            sighash, script
+        ))
+        .into());
     }
     Ok(())
 }
+
+/// Determine if all not yet finalized PSBT inputs are signed with the default
+/// sighash or one of the explicitly allowed ones.
+pub(crate) fn check_allowed_sighashes(
+    psbt: &Psbt,
+    allowed: &[PsbtSighashType],
+) -> Result<(), anyhow::Error> {
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let Some(sighash) = input.sighash_type else {
+            continue;
+        };
+        if sighash == EcdsaSighashType::All.into()
+            || sighash == TapSighashType::Default.into()
+            || allowed.contains(&sighash)
+        {
+            continue;
+        }
+        return Err(RuntimeError::usage(format!(
+            "input {} requests {} sighash, use --allow_sighash to sign it",
+            index, sighash
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Decode a PSBT of version 0 or version 2 (BIP370), returning it together with its version.
+pub(crate) fn decode_psbt(data: &[u8]) -> Result<(Psbt, PsbtVer), anyhow::Error> {
+    let err = match Psbt::deserialize(data) {
+        Ok(psbt) => return Ok((psbt, PsbtVer::V0)),
+        Err(err) => err,
+    };
+    match psbt::Psbt::deserialize(data) {
+        Ok(psbt) if psbt.version == PsbtVer::V2 => {
+            let psbt_v0 = Psbt::deserialize(&psbt.serialize(PsbtVer::V0))?;
+            Ok((psbt_v0, PsbtVer::V2))
+        }
+        _ => Err(err.into()),
+    }
+}
+
+/// Encode a PSBT with the requested version.
+pub(crate) fn encode_psbt(psbt: &Psbt, version: PsbtVer) -> Result<Vec<u8>, anyhow::Error> {
+    let data = psbt.serialize();
+    match version {
+        PsbtVer::V0 => Ok(data),
+        PsbtVer::V2 => Ok(psbt::Psbt::deserialize(data)
+            .map_err(|err| {
+                RuntimeError::Wallet(bdk::Error::Generic(format!(
+                    "unable to convert PSBT to {}: {}",
+                    version, err
+                )))
+            })?
+            .serialize(PsbtVer::V2)),
+    }
+}
+
+/// Read a PSBT file of version 0 or version 2.
+pub(crate) fn read_psbt(path: impl AsRef<Path>) -> Result<(Psbt, PsbtVer), anyhow::Error> {
+    decode_psbt(&std::fs::read(path)?)
+}
+
+/// Write a PSBT file with the requested version, returning the written data.
+pub(crate) fn write_psbt(
+    path: impl AsRef<Path>,
+    psbt: &Psbt,
+    version: PsbtVer,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let data = encode_psbt(psbt, version)?;
+    std::fs::write(path, &data)?;
+    Ok(data)
+}
+
+/// Convert a BDK outpoint into the outpoint type used by the RGB libraries.
+pub(crate) fn to_rgb_outpoint(outpoint: OutPoint) -> rgbstd::Outpoint {
+    rgbstd::Outpoint::new(bp::Txid::from(outpoint.txid.to_byte_array()), outpoint.vout)
+}
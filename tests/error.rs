@@ -0,0 +1,51 @@
+//! Checks the exit codes of the errors returned by the commands.
+
+use std::io;
+
+use bihelix_rgb_cli::cmds::runtime::RuntimeError;
+use bihelix_rgb_cli::error::ErrorKind;
+use rgb::validation::{Failure, Status};
+
+fn classify(err: impl Into<anyhow::Error>) -> (ErrorKind, i32) {
+    let err = RuntimeError::classify(err.into());
+    (err.kind(), err.exit_code())
+}
+
+#[test]
+fn usage_errors() {
+    assert_eq!(
+        classify(RuntimeError::usage("no wallet")),
+        (ErrorKind::Usage, 2)
+    );
+    let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+    assert_eq!(classify(json), (ErrorKind::Usage, 2));
+}
+
+#[test]
+fn network_errors() {
+    assert_eq!(
+        classify(RuntimeError::network("proxy is down")),
+        (ErrorKind::Network, 4)
+    );
+    let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+    assert_eq!(classify(refused), (ErrorKind::Network, 4));
+}
+
+#[test]
+fn validation_errors() {
+    let status = Status::with_failure(Failure::NetworkMismatch(true));
+    assert_eq!(
+        classify(RuntimeError::InvalidConsignment(status)),
+        (ErrorKind::InvalidConsignment, 5)
+    );
+}
+
+#[test]
+fn other_errors() {
+    let not_found = io::Error::from(io::ErrorKind::NotFound);
+    assert_eq!(classify(not_found), (ErrorKind::Io, 3));
+    assert_eq!(
+        classify(anyhow::anyhow!("unexpected")),
+        (ErrorKind::Internal, 1)
+    );
+}
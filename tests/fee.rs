@@ -0,0 +1,149 @@
+//! Compares the weight estimations with the weight of transactions signed
+//! with signatures of the maximal size.
+
+use bdk::bitcoin::absolute::LockTime;
+use bdk::bitcoin::script::{Builder, PushBytesBuf};
+use bdk::bitcoin::{
+    Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness,
+};
+use bdk::database::MemoryDatabase;
+use bdk::{KeychainKind, Wallet};
+use bihelix_rgb_cli::fee::{InputType, InputWeight, TxWeight};
+
+const TPUB: &str = "tpubD6NzVbkrYhZ4WaWSyoBvQwbpLkojyoTZPRsgXELWz3Popb3qkjcJyJUGLnL4qHHoQvao8ESaAstxYSnhyswJ76uZPStJRJCTKvosUCJZL5B";
+
+/// DER signature of the maximal size with the sighash suffix.
+fn ecdsa_sig() -> Vec<u8> {
+    vec![0x30; 72]
+}
+
+/// Schnorr signature with a sighash suffix.
+fn schnorr_sig() -> Vec<u8> {
+    vec![0x01; 65]
+}
+
+fn pubkey() -> Vec<u8> {
+    let mut pubkey = vec![0x11; 33];
+    pubkey[0] = 0x02;
+    pubkey
+}
+
+fn push(data: Vec<u8>) -> PushBytesBuf {
+    PushBytesBuf::try_from(data).unwrap()
+}
+
+/// Input of the given type signed with signatures of the maximal size.
+fn signed_input(input_type: InputType) -> TxIn {
+    let (script_sig, witness) = match input_type {
+        InputType::Pkh => (
+            Builder::new()
+                .push_slice(push(ecdsa_sig()))
+                .push_slice(push(pubkey()))
+                .into_script(),
+            Witness::new(),
+        ),
+        InputType::Wpkh => (
+            ScriptBuf::new(),
+            Witness::from_slice(&[ecdsa_sig(), pubkey()]),
+        ),
+        InputType::ShWpkh => {
+            let mut redeem_script = vec![0x00, 0x14];
+            redeem_script.extend([0x22; 20]);
+            (
+                Builder::new().push_slice(push(redeem_script)).into_script(),
+                Witness::from_slice(&[ecdsa_sig(), pubkey()]),
+            )
+        }
+        InputType::Tr => (ScriptBuf::new(), Witness::from_slice(&[schnorr_sig()])),
+    };
+    TxIn {
+        previous_output: OutPoint::null(),
+        script_sig,
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness,
+    }
+}
+
+fn output(input_type: InputType) -> TxOut {
+    TxOut {
+        value: 10_000,
+        script_pubkey: ScriptBuf::from(vec![0x00; input_type.script_len()]),
+    }
+}
+
+fn transaction(inputs: &[InputType], outputs: &[InputType]) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: inputs.iter().copied().map(signed_input).collect(),
+        output: outputs.iter().copied().map(output).collect(),
+    }
+}
+
+fn estimate(inputs: &[InputType], outputs: &[InputType]) -> Weight {
+    let mut weight = TxWeight::new();
+    for input in inputs {
+        weight.add_input(input.to_input_weight());
+    }
+    for output in outputs {
+        weight.add_output(output.script_len());
+    }
+    weight.weight()
+}
+
+fn assert_weight(inputs: &[InputType], outputs: &[InputType]) {
+    assert_eq!(
+        estimate(inputs, outputs),
+        transaction(inputs, outputs).weight(),
+        "inputs {inputs:?}, outputs {outputs:?}"
+    );
+}
+
+#[test]
+fn single_type_transactions() {
+    for input_type in [
+        InputType::Pkh,
+        InputType::Wpkh,
+        InputType::ShWpkh,
+        InputType::Tr,
+    ] {
+        assert_weight(&[input_type], &[input_type]);
+        assert_weight(&[input_type, input_type], &[input_type, InputType::Wpkh]);
+    }
+}
+
+#[test]
+fn mixed_transactions() {
+    assert_weight(&[InputType::Pkh, InputType::Wpkh], &[InputType::Tr]);
+    assert_weight(
+        &[InputType::Tr, InputType::ShWpkh, InputType::Pkh],
+        &[InputType::Pkh, InputType::Wpkh, InputType::ShWpkh],
+    );
+}
+
+#[test]
+fn wallet_input_weights() {
+    for (descriptor, input_type) in [
+        ("pkh", InputType::Pkh),
+        ("wpkh", InputType::Wpkh),
+        ("sh-wpkh", InputType::ShWpkh),
+        ("tr", InputType::Tr),
+    ] {
+        let descriptor = match descriptor {
+            "sh-wpkh" => format!("sh(wpkh({TPUB}/0/*))"),
+            name => format!("{name}({TPUB}/0/*)"),
+        };
+        let wallet = Wallet::new(
+            &descriptor,
+            None,
+            Network::Testnet,
+            MemoryDatabase::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            InputWeight::from_wallet(&wallet, KeychainKind::External).unwrap(),
+            input_type.to_input_weight(),
+            "{descriptor}"
+        );
+    }
+}
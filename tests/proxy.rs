@@ -0,0 +1,448 @@
+//! Sends a transfer consignment from a payer stash to a payee stash through
+//! an RGB proxy running in the test process.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bdk::bitcoin::Network;
+use bihelix_rgb_cli::cmds::proxy::{recipient_id, ProxyClient};
+use bihelix_rgb_cli::cmds::runtime::Runtime;
+use bitcoin::absolute::LockTime;
+use bitcoin::opcodes::all::OP_RETURN;
+use bitcoin::script::Builder;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+use psbt::Psbt;
+use rgbstd::containers::{Bindle, Transfer};
+use rgbstd::invoice::{Beneficiary, RgbInvoice};
+use rgbstd::persistence::Inventory;
+use rgbstd::{ContractId, XChain};
+use serde_json::{json, Value as JsonValue};
+
+const ISSUE_TXID: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+const PAYEE_TXID: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+/// Consignment and ACK kept by the proxy for a recipient.
+#[derive(Clone, Default)]
+struct Slot {
+    consignment: Option<Vec<u8>>,
+    ack: Option<bool>,
+}
+
+/// JSON-RPC RGB proxy serving the `consignment.*` and `ack.*` methods.
+struct MockProxy {
+    port: u16,
+    slots: Arc<Mutex<BTreeMap<String, Slot>>>,
+}
+
+impl MockProxy {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind proxy");
+        let port = listener.local_addr().expect("proxy address").port();
+        let slots = Arc::new(Mutex::new(BTreeMap::new()));
+        let served = slots.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                handle(stream, &served);
+            }
+        });
+        MockProxy { port, slots }
+    }
+
+    fn url(&self) -> String {
+        format!("rpc://127.0.0.1:{}/json-rpc", self.port)
+    }
+
+    fn slot(&self, recipient_id: &str) -> Slot {
+        let slots = self.slots.lock().unwrap();
+        slots.get(recipient_id).cloned().unwrap_or_default()
+    }
+}
+
+fn handle(stream: TcpStream, slots: &Mutex<BTreeMap<String, Slot>>) {
+    let mut reader = BufReader::new(&stream);
+    let mut content_type = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or_default() == 0 {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "content-type" => content_type = value.trim().to_owned(),
+                "content-length" => content_length = value.trim().parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).expect("request body");
+
+    let (method, recipient_id, params, file) =
+        match content_type.strip_prefix("multipart/form-data; boundary=") {
+            Some(boundary) => {
+                let fields = parse_multipart(&body, boundary);
+                let text = |name: &str| String::from_utf8(fields[name].clone()).unwrap();
+                (
+                    text("method"),
+                    text("params[recipient_id]"),
+                    JsonValue::Null,
+                    fields.get("file").cloned(),
+                )
+            }
+            None => {
+                let request = serde_json::from_slice::<JsonValue>(&body).expect("JSON-RPC request");
+                let params = request["params"].clone();
+                (
+                    request["method"].as_str().unwrap().to_owned(),
+                    params["recipient_id"].as_str().unwrap().to_owned(),
+                    params,
+                    None,
+                )
+            }
+        };
+
+    let mut slots = slots.lock().unwrap();
+    let slot = slots.entry(recipient_id).or_default();
+    let result = match method.as_str() {
+        "consignment.post" => {
+            slot.consignment = file;
+            json!(true)
+        }
+        "consignment.get" => match &slot.consignment {
+            Some(consignment) => json!({ "consignment": base64::encode(consignment) }),
+            None => JsonValue::Null,
+        },
+        "ack.post" => {
+            slot.ack = params["ack"].as_bool();
+            json!(true)
+        }
+        "ack.get" => json!(slot.ack),
+        _ => panic!("unexpected proxy method {method}"),
+    };
+    drop(slots);
+
+    let response = json!({ "jsonrpc": "2.0", "id": "1", "result": result }).to_string();
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{response}",
+        response.len()
+    )
+    .expect("proxy response");
+}
+
+/// Fields of a multipart form by their names.
+fn parse_multipart(body: &[u8], boundary: &str) -> BTreeMap<String, Vec<u8>> {
+    let delimiter = format!("--{boundary}");
+    let mut fields = BTreeMap::new();
+    for part in split(body, delimiter.as_bytes()) {
+        let Some(headers_end) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..headers_end]);
+        let Some(name) = headers
+            .split("name=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+        else {
+            continue;
+        };
+        let value = &part[headers_end + 4..];
+        let value = value.strip_suffix(b"\r\n").unwrap_or(value);
+        fields.insert(name.to_owned(), value.to_vec());
+    }
+    fields
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn split<'a>(mut haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = vec![];
+    while let Some(pos) = find(haystack, needle) {
+        parts.push(&haystack[..pos]);
+        haystack = &haystack[pos + needle.len()..];
+    }
+    parts.push(haystack);
+    parts
+}
+
+fn cli_command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bihelix-rgb-cli"));
+    command.arg("rgb").arg("-d").arg(dir).args(args);
+    command
+}
+
+fn parse_output(output: Output, args: &[&str]) -> JsonValue {
+    assert!(
+        output.status.success(),
+        "rgb {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).expect("JSON output")
+}
+
+fn cli(dir: &Path, args: &[&str]) -> JsonValue {
+    let output = cli_command(dir, args).output().expect("run the tool");
+    parse_output(output, args)
+}
+
+fn spawn_cli(dir: &Path, args: &[&str]) -> Child {
+    cli_command(dir, args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run the tool")
+}
+
+/// Payer holding an issued contract and payee with an invoice for it.
+struct Setup {
+    dir: PathBuf,
+    payer: PathBuf,
+    payee: PathBuf,
+    contract_id: String,
+    invoice: RgbInvoice,
+}
+
+impl Setup {
+    fn new(name: &str, proxy: &MockProxy) -> Self {
+        let dir = std::env::temp_dir().join(format!("bihelix-proxy-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let payer = dir.join("payer");
+        let payee = dir.join("payee");
+        cli(&payer, &["init"]);
+        cli(&payee, &["init"]);
+
+        let issued = cli(
+            &payer,
+            &[
+                "issue-rgb20",
+                "--ticker",
+                "TST",
+                "--name",
+                "Test asset",
+                "-a",
+                &format!("{ISSUE_TXID}:0=1000"),
+            ],
+        );
+        let contract_id = issued["contract_id"].as_str().unwrap().to_owned();
+        let contract = dir.join("contract.rgb");
+        cli(
+            &payer,
+            &["export", &contract_id, contract.to_str().unwrap()],
+        );
+        cli(&payee, &["import", contract.to_str().unwrap()]);
+
+        let invoice = cli(
+            &payee,
+            &[
+                "invoice",
+                &contract_id,
+                "RGB20",
+                "100",
+                &format!("{PAYEE_TXID}:0"),
+                "--proxy",
+                &proxy.url(),
+            ],
+        );
+        let invoice = RgbInvoice::from_str(invoice["invoice"].as_str().unwrap()).unwrap();
+        Setup {
+            dir,
+            payer,
+            payee,
+            contract_id,
+            invoice,
+        }
+    }
+
+    /// Pays the invoice from the issued allocation and saves the transfer
+    /// consignment, with its witness transaction in the terminal.
+    fn transfer(&self) -> PathBuf {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(&format!("{ISSUE_TXID}:0")).unwrap(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+                },
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: ScriptBuf::from_hex(
+                        "00140000000000000000000000000000000000000000",
+                    )
+                    .unwrap(),
+                },
+            ],
+        };
+        let mut psbt = bitcoin::psbt::Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(20_000),
+            script_pubkey: ScriptBuf::from_hex("00141111111111111111111111111111111111111111")
+                .unwrap(),
+        });
+        let psbt_file = self.dir.join("transfer.psbt");
+        fs::write(&psbt_file, psbt.serialize()).unwrap();
+        let psbt_path = psbt_file.to_str().unwrap();
+
+        cli(&self.payer, &["set-host", psbt_path]);
+        cli(
+            &self.payer,
+            &[
+                "psbt-add-transition",
+                "--psbt",
+                psbt_path,
+                "--contract",
+                &self.contract_id,
+                "--iface",
+                "RGB20",
+                "--from",
+                &format!("{ISSUE_TXID}:0"),
+                "--to",
+                &format!("{}=100", recipient_id(&self.invoice)),
+                "--to",
+                "1=900",
+            ],
+        );
+        cli(&self.payer, &["commit", "--psbt", psbt_path]);
+
+        let Beneficiary::BlindedSeal(seal) = self.invoice.beneficiary.into_inner() else {
+            panic!("the invoice has no blinded seal");
+        };
+        let runtime = Runtime::load(self.payer.clone(), &Network::Testnet).unwrap();
+        let contract_id = ContractId::from_str(&self.contract_id).unwrap();
+        let mut transfer: Transfer = runtime
+            .transfer(contract_id, [], [XChain::Bitcoin(seal)])
+            .unwrap()
+            .unbindle();
+        let psbt = Psbt::deserialize(fs::read(&psbt_file).unwrap()).unwrap();
+        let witness = bp::Tx::from(psbt.to_unsigned_tx());
+        let bundle_ids = transfer.terminals.keys().copied().collect::<Vec<_>>();
+        for bundle_id in bundle_ids {
+            if let Some(XChain::Bitcoin(terminal)) = transfer.terminals.get_mut(&bundle_id) {
+                terminal.tx = Some(witness.clone());
+            }
+        }
+
+        let file = self.dir.join("transfer.rgb");
+        Bindle::new(transfer).save(&file).unwrap();
+        file
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn send_receive_ack() {
+    let proxy = MockProxy::start();
+    let setup = Setup::new("ack", &proxy);
+    let recipient_id = recipient_id(&setup.invoice);
+    let file = setup.transfer();
+
+    let invoice = setup.invoice.to_string();
+    let send_args = [
+        "send",
+        &invoice,
+        file.to_str().unwrap(),
+        "--wait",
+        "--timeout",
+        "60",
+    ];
+    let sender = spawn_cli(&setup.payer, &send_args);
+
+    let started = Instant::now();
+    while proxy.slot(&recipient_id).consignment.is_none() {
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "consignment is not posted"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(
+        proxy.slot(&recipient_id).consignment.unwrap(),
+        fs::read(&file).unwrap()
+    );
+
+    let received = cli(&setup.payee, &["receive", &invoice, "--queue"]);
+    assert_eq!(received["ack"], json!(true));
+    assert_eq!(received["pending"]["status"], json!("waiting"));
+    assert_eq!(proxy.slot(&recipient_id).ack, Some(true));
+
+    let sent = parse_output(sender.wait_with_output().unwrap(), &send_args);
+    assert_eq!(sent["recipient_id"], json!(recipient_id));
+    assert_eq!(sent["ack"], json!(true));
+}
+
+#[test]
+fn receive_invalid_consignment_nack() {
+    let proxy = MockProxy::start();
+    let setup = Setup::new("nack", &proxy);
+    let recipient_id = recipient_id(&setup.invoice);
+
+    let client = ProxyClient::for_invoice(None, &setup.invoice).unwrap();
+    let txid = bdk::bitcoin::Txid::from_str(ISSUE_TXID).unwrap();
+    client
+        .post_consignment(&recipient_id, txid, b"not a consignment")
+        .unwrap();
+
+    let invoice = setup.invoice.to_string();
+    let output = cli_command(&setup.payee, &["--json-errors", "receive", &invoice])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error = serde_json::from_str::<JsonValue>(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["error"]["kind"], json!("invalid_consignment"));
+    assert_eq!(proxy.slot(&recipient_id).ack, Some(false));
+    assert_eq!(client.get_ack(&recipient_id).unwrap(), Some(false));
+}
+
+#[test]
+fn receive_accept_ack() {
+    let proxy = MockProxy::start();
+    let setup = Setup::new("accept", &proxy);
+    let recipient_id = recipient_id(&setup.invoice);
+    let file = setup.transfer();
+
+    let client = ProxyClient::for_invoice(None, &setup.invoice).unwrap();
+    let txid = bdk::bitcoin::Txid::from_str(ISSUE_TXID).unwrap();
+    client
+        .post_consignment(&recipient_id, txid, &fs::read(&file).unwrap())
+        .unwrap();
+
+    let invoice = setup.invoice.to_string();
+    let received = cli(&setup.payee, &["receive", &invoice]);
+    assert_eq!(received["ack"], json!(true));
+    assert_eq!(received["contract_id"], json!(setup.contract_id));
+    assert_eq!(received["validity"], json!("valid"));
+    assert_eq!(proxy.slot(&recipient_id).ack, Some(true));
+}